@@ -0,0 +1,494 @@
+use serde_yaml::Value;
+use std::fmt;
+use std::ops::Range;
+
+use crate::yaml_lines::{indent, key_of, significant, strip_comment};
+
+/// A single Casbin rule from an ArgoCD `policy.csv`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyRule {
+    /// `p, subject, resource, action, object, effect`
+    Policy {
+        subject: String,
+        resource: String,
+        action: String,
+        object: String,
+        effect: String,
+    },
+    /// `g, subject, role`
+    Grouping { subject: String, role: String },
+}
+
+impl PolicyRule {
+    //parse a csv line, none for comments, blank and malformed lines
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields = line.split(',').map(str::trim).collect::<Vec<&str>>();
+        match fields.as_slice() {
+            ["p", subject, resource, action, object, effect] => Some(Self::Policy {
+                subject: subject.to_string(),
+                resource: resource.to_string(),
+                action: action.to_string(),
+                object: object.to_string(),
+                effect: effect.to_string(),
+            }),
+            ["g", subject, role] => Some(Self::Grouping {
+                subject: subject.to_string(),
+                role: role.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    //rules granting or binding the role of a project
+    fn is_owned_by(&self, role: &str) -> bool {
+        match self {
+            Self::Policy { subject, .. } => subject == role,
+            Self::Grouping { role: bound, .. } => bound == role,
+        }
+    }
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Policy {
+                subject,
+                resource,
+                action,
+                object,
+                effect,
+            } => write!(f, "p, {subject}, {resource}, {action}, {object}, {effect}"),
+            Self::Grouping { subject, role } => write!(f, "g, {subject}, {role}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PolicyLine {
+    /// Rule with its original text, kept as written unless the rule changes
+    Rule(PolicyRule, String),
+    /// Comment, blank or unparsable line
+    Other(String),
+}
+
+/// Parsed `policy.csv`, keeping comments and the order of lines
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    lines: Vec<PolicyLine>,
+}
+
+impl Policy {
+    pub fn parse(csv: &str) -> Self {
+        let lines = csv
+            .lines()
+            .map(|line| match PolicyRule::parse(line) {
+                Some(rule) => PolicyLine::Rule(rule, line.to_string()),
+                None => PolicyLine::Other(line.to_string()),
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &PolicyRule> {
+        self.lines.iter().filter_map(|line| match line {
+            PolicyLine::Rule(rule, _) => Some(rule),
+            PolicyLine::Other(_) => None,
+        })
+    }
+
+    //replace the rules of a project with a managed block, skipping rules already granted elsewhere
+    pub fn set_project(&mut self, name: &str, rules: Vec<PolicyRule>) {
+        self.remove_project(name);
+        let (begin, end) = block_markers(name);
+        let mut block = vec![PolicyLine::Other(begin)];
+        for rule in rules {
+            let duplicate = self.rules().any(|existing| existing == &rule)
+                || block
+                    .iter()
+                    .any(|line| matches!(line, PolicyLine::Rule(existing, _) if existing == &rule));
+            if !duplicate {
+                let text = rule.to_string();
+                block.push(PolicyLine::Rule(rule, text));
+            }
+        }
+        block.push(PolicyLine::Other(end));
+        //keep a trailing blank line at the end of the policy
+        let at = self
+            .lines
+            .iter()
+            .rposition(|line| !matches!(line, PolicyLine::Other(text) if text.trim().is_empty()))
+            .map_or(0, |position| position + 1);
+        self.lines.splice(at..at, block);
+    }
+
+    //remove the managed block of a project and any rules for its role outside of it
+    pub fn remove_project(&mut self, name: &str) {
+        let (begin, end) = block_markers(name);
+        let role = format!("role:{name}");
+        let mut in_block = false;
+        self.lines.retain(|line| match line {
+            PolicyLine::Other(text) if text.trim() == begin => {
+                in_block = true;
+                false
+            }
+            PolicyLine::Other(text) if in_block && text.trim() == end => {
+                in_block = false;
+                false
+            }
+            _ if in_block => false,
+            PolicyLine::Rule(rule, _) => !rule.is_owned_by(&role),
+            PolicyLine::Other(_) => true,
+        });
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                PolicyLine::Rule(_, text) | PolicyLine::Other(text) => writeln!(f, "{text}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn block_markers(name: &str) -> (String, String) {
+    (
+        format!("# BEGIN kyotu:{name}"),
+        format!("# END kyotu:{name}"),
+    )
+}
+
+//parse rules rendered from rbac_tmpl.yaml
+pub fn parse_rules(csv: &str) -> Vec<PolicyRule> {
+    csv.lines().filter_map(PolicyRule::parse).collect()
+}
+
+/// ArgoCD RBAC manifest, either an `ArgoCD` resource or the `argocd-rbac-cm` ConfigMap
+///
+/// Only the lines of the policy csv are rewritten,
+/// every other line, comment and quoting is written back as it was read.
+pub struct RbacManifest {
+    document: Value,
+    lines: Vec<String>,
+    shape: Shape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    ArgoCd,
+    ConfigMap,
+}
+
+impl Shape {
+    //keys leading to the policy csv
+    fn path(self) -> &'static [&'static str] {
+        match self {
+            Self::ArgoCd => &["spec", "rbac", "policy"],
+            Self::ConfigMap => &["data", "policy.csv"],
+        }
+    }
+}
+
+impl RbacManifest {
+    pub fn parse(yaml: &str) -> Result<Self, ArgoRbacError> {
+        let document: Value =
+            serde_yaml::from_str(yaml).map_err(|e| ArgoRbacError::ParseError(e.to_string()))?;
+        let shape = match document.get("kind").and_then(Value::as_str) {
+            Some("ArgoCD") => Shape::ArgoCd,
+            Some("ConfigMap") => Shape::ConfigMap,
+            kind => {
+                return Err(ArgoRbacError::UnsupportedKind(
+                    kind.unwrap_or_default().to_string(),
+                ))
+            }
+        };
+        Ok(Self {
+            document,
+            lines: yaml.split('\n').map(str::to_string).collect(),
+            shape,
+        })
+    }
+
+    //current policy, empty when the manifest has none yet
+    pub fn policy(&self) -> Result<Policy, ArgoRbacError> {
+        let value = self
+            .shape
+            .path()
+            .iter()
+            .try_fold(&self.document, |value, key| value.get(key));
+        match value {
+            None | Some(Value::Null) => Ok(Policy::parse("")),
+            Some(Value::String(csv)) => Ok(Policy::parse(csv)),
+            Some(_) => Err(ArgoRbacError::ParseError(format!(
+                "{} is not a string",
+                self.shape.path().join(".")
+            ))),
+        }
+    }
+
+    //write the policy as a literal block scalar, creating missing keys
+    pub fn set_policy(&mut self, policy: &Policy) -> Result<(), ArgoRbacError> {
+        if self.policy()? == *policy {
+            return Ok(());
+        }
+        let (key, parents) = self.shape.path().split_last().unwrap();
+        let mut parent = None;
+        for key in parents {
+            parent = Some(self.mapping(parent, key)?);
+        }
+        let (key_line, content_indent) = match self.child(parent, key) {
+            Some(line) => {
+                let (key_indent, _, value) = key_of(&self.lines[line]).unwrap();
+                let content = self.value_range(line);
+                let literal = strip_comment(value)
+                    .strip_prefix('|')
+                    .is_some_and(|chomping| matches!(chomping, "" | "-" | "+"));
+                let content_indent = content
+                    .clone()
+                    .find(|&line| !self.lines[line].trim().is_empty())
+                    .filter(|_| literal)
+                    .map_or(key_indent + 2, |line| indent(&self.lines[line]));
+                if !literal {
+                    let colon = self.lines[line].find(':').unwrap();
+                    self.lines[line].truncate(colon + 1);
+                    self.lines[line].push_str(" |");
+                }
+                self.lines.drain(content);
+                (line, content_indent)
+            }
+            None => {
+                let (at, key_indent) = self.insertion(parent);
+                self.lines
+                    .insert(at, format!("{}{key}: |", " ".repeat(key_indent)));
+                (at, key_indent + 2)
+            }
+        };
+        let csv = policy.to_string();
+        let content = csv.lines().map(|line| match line {
+            "" => String::new(),
+            line => format!("{}{line}", " ".repeat(content_indent)),
+        });
+        self.lines
+            .splice(key_line + 1..key_line + 1, content.collect::<Vec<String>>());
+        self.document = serde_yaml::from_str(&self.lines.join("\n"))
+            .map_err(|e| ArgoRbacError::ParseError(e.to_string()))?;
+        Ok(())
+    }
+
+    pub fn to_yaml(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    //line of a mapping key under parent, the document root when none, adding it when missing
+    fn mapping(&mut self, parent: Option<usize>, key: &str) -> Result<usize, ArgoRbacError> {
+        let Some(line) = self.child(parent, key) else {
+            let (at, key_indent) = self.insertion(parent);
+            self.lines
+                .insert(at, format!("{}{key}:", " ".repeat(key_indent)));
+            return Ok(at);
+        };
+        match strip_comment(key_of(&self.lines[line]).unwrap().2) {
+            "" => Ok(line),
+            "{}" => {
+                let colon = self.lines[line].find(':').unwrap();
+                self.lines[line].truncate(colon + 1);
+                Ok(line)
+            }
+            _ => Err(ArgoRbacError::ParseError(format!("{key} is not a mapping"))),
+        }
+    }
+
+    //lines holding the value of a key, or the whole document
+    fn children(&self, parent: Option<usize>) -> Range<usize> {
+        match parent {
+            Some(line) => self.value_range(line),
+            None => 0..self.lines.len(),
+        }
+    }
+
+    fn child(&self, parent: Option<usize>, key: &str) -> Option<usize> {
+        let range = self.children(parent);
+        let first = range.clone().find(|&line| significant(&self.lines[line]))?;
+        let child_indent = indent(&self.lines[first]);
+        range.into_iter().find(|&line| {
+            significant(&self.lines[line])
+                && indent(&self.lines[line]) == child_indent
+                && key_of(&self.lines[line]).is_some_and(|(_, found, _)| found == key)
+        })
+    }
+
+    //line and indentation of a new key appended to a mapping
+    fn insertion(&self, parent: Option<usize>) -> (usize, usize) {
+        let range = self.children(parent);
+        let child_indent = range
+            .clone()
+            .find(|&line| significant(&self.lines[line]))
+            .map(|line| indent(&self.lines[line]))
+            .unwrap_or_else(|| parent.map_or(0, |line| indent(&self.lines[line]) + 2));
+        let at = range
+            .clone()
+            .rev()
+            .find(|&line| !self.lines[line].trim().is_empty())
+            .map_or(range.start, |line| line + 1);
+        (at, child_indent)
+    }
+
+    //lines after a key indented deeper than it, up to the last non blank one
+    fn value_range(&self, key_line: usize) -> Range<usize> {
+        let key_indent = indent(&self.lines[key_line]);
+        let mut end = key_line + 1;
+        for line in key_line + 1..self.lines.len() {
+            let text = &self.lines[line];
+            if text.trim().is_empty() {
+                continue;
+            }
+            if indent(text) <= key_indent {
+                break;
+            }
+            end = line + 1;
+        }
+        key_line + 1..end
+    }
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum ArgoRbacError {
+    #[error("Could not parse ArgoCD rbac manifest: {0}")]
+    ParseError(String),
+    #[error("Unsupported ArgoCD rbac manifest kind `{0}`, expected ArgoCD or ConfigMap")]
+    UnsupportedKind(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CR: &str = include_str!("../tests/fixtures/argocd_cr_rbac.yaml");
+    const CONFIGMAP: &str = include_str!("../tests/fixtures/argocd_rbac_cm.yaml");
+    const TEMPLATE: &str = "    p, role:demo-dev, applications, get, demo-dev/*, allow \n    p, role:demo-dev, logs, get, demo-dev/*, allow\n    p, role:demo-dev, logs, get, demo-dev/*, allow\n    g, demo@kyotu.tech, role:demo-dev\n";
+
+    fn policy_csv(manifest: &RbacManifest) -> String {
+        manifest.policy().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            PolicyRule::parse(" p, role:demo-dev, logs, get, demo-dev/*, allow "),
+            Some(PolicyRule::Policy {
+                subject: "role:demo-dev".to_string(),
+                resource: "logs".to_string(),
+                action: "get".to_string(),
+                object: "demo-dev/*".to_string(),
+                effect: "allow".to_string(),
+            })
+        );
+        assert_eq!(
+            PolicyRule::parse("g,demo@kyotu.tech,role:demo-dev"),
+            Some(PolicyRule::Grouping {
+                subject: "demo@kyotu.tech".to_string(),
+                role: "role:demo-dev".to_string(),
+            })
+        );
+        assert_eq!(PolicyRule::parse("# p, role:x, a, b, c, allow"), None);
+        assert_eq!(PolicyRule::parse("p, role:x, a"), None);
+    }
+
+    #[test]
+    fn test_add_and_remove_project_in_both_shapes() {
+        for fixture in [CR, CONFIGMAP] {
+            let mut manifest = RbacManifest::parse(fixture).unwrap();
+            let original = policy_csv(&manifest);
+
+            let mut policy = manifest.policy().unwrap();
+            policy.set_project("demo-dev", parse_rules(TEMPLATE));
+            manifest.set_policy(&policy).unwrap();
+            let manifest = RbacManifest::parse(&manifest.to_yaml()).unwrap();
+            assert_eq!(
+                policy_csv(&manifest),
+                format!(
+                    "{original}# BEGIN kyotu:demo-dev\np, role:demo-dev, applications, get, demo-dev/*, allow\np, role:demo-dev, logs, get, demo-dev/*, allow\ng, demo@kyotu.tech, role:demo-dev\n# END kyotu:demo-dev\n"
+                )
+            );
+
+            //adding again replaces the block instead of appending a second one
+            let mut policy = manifest.policy().unwrap();
+            policy.set_project("demo-dev", parse_rules(TEMPLATE));
+            assert_eq!(policy, manifest.policy().unwrap());
+
+            policy.remove_project("demo-dev");
+            assert_eq!(policy.to_string(), original);
+        }
+    }
+
+    #[test]
+    fn test_remove_project_without_block() {
+        let manifest = RbacManifest::parse(CONFIGMAP).unwrap();
+        let mut policy = manifest.policy().unwrap();
+        policy.remove_project("shop-dev");
+        assert_eq!(
+            policy.to_string(),
+            "# platform team\np, role:platform, applications, *, */*, allow\ng, platform@kyotu.tech, role:platform\n"
+        );
+    }
+
+    #[test]
+    fn test_set_project_skips_rules_granted_elsewhere() {
+        let mut policy = RbacManifest::parse(CR).unwrap().policy().unwrap();
+        let shared = PolicyRule::Grouping {
+            subject: "platform@kyotu.tech".to_string(),
+            role: "role:platform".to_string(),
+        };
+        policy.set_project("demo-dev", vec![shared.clone()]);
+        assert_eq!(policy.rules().filter(|rule| *rule == &shared).count(), 1);
+        policy.remove_project("demo-dev");
+        assert!(policy.rules().any(|rule| rule == &shared));
+    }
+
+    #[test]
+    fn test_commented_manifest_round_trips() {
+        let commented = "# managed by the platform team\napiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: argocd-rbac-cm\n  annotations: {note: \"kept as written\"} # flow mapping\ndata:\n  # readonly for everyone else\n  policy.default: 'role:readonly'\n  policy.csv: |\n    # platform team  \n    p, role:platform, applications, *, */*, allow\n\n    g,platform@kyotu.tech,role:platform\n  scopes: >-\n    [groups]\n";
+        let mut manifest = RbacManifest::parse(commented).unwrap();
+        let original = manifest.policy().unwrap();
+        manifest.set_policy(&original).unwrap();
+        assert_eq!(manifest.to_yaml(), commented);
+
+        let mut policy = original.clone();
+        policy.set_project("demo-dev", parse_rules(TEMPLATE));
+        manifest.set_policy(&policy).unwrap();
+        assert_eq!(
+            manifest.to_yaml(),
+            commented.replace(
+                "role:platform\n  scopes",
+                "role:platform\n    # BEGIN kyotu:demo-dev\n    p, role:demo-dev, applications, get, demo-dev/*, allow\n    p, role:demo-dev, logs, get, demo-dev/*, allow\n    g, demo@kyotu.tech, role:demo-dev\n    # END kyotu:demo-dev\n  scopes"
+            )
+        );
+
+        policy.remove_project("demo-dev");
+        manifest.set_policy(&policy).unwrap();
+        assert_eq!(manifest.to_yaml(), commented);
+    }
+
+    #[test]
+    fn test_unsupported_kind() {
+        assert!(matches!(
+            RbacManifest::parse("kind: Secret\n"),
+            Err(ArgoRbacError::UnsupportedKind(kind)) if kind == "Secret"
+        ));
+    }
+
+    #[test]
+    fn test_set_policy_creates_missing_keys() {
+        let mut manifest =
+            RbacManifest::parse("kind: ConfigMap\nmetadata:\n  name: argocd-rbac-cm\n").unwrap();
+        let mut policy = manifest.policy().unwrap();
+        policy.set_project("demo-dev", parse_rules(TEMPLATE));
+        manifest.set_policy(&policy).unwrap();
+        assert!(manifest
+            .to_yaml()
+            .contains("data:\n  policy.csv: |\n    # BEGIN kyotu:demo-dev\n"));
+    }
+}
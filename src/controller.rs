@@ -1,49 +1,122 @@
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
+use k8s_openapi::api::core::v1::{Namespace, Secret};
 use kube::{
     api::Api,
     client::Client,
     runtime::{
         controller::{Action, Controller},
         events::{Event, EventType, Recorder, Reporter},
-        watcher::Config,
+        reflector::{self, reflector, ObjectRef},
+        watcher::watcher,
+        WatchStreamExt,
     },
-    Resource,
+    Resource, ResourceExt,
 };
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::{sync::RwLock, time::Duration};
 use tracing::info;
 
-use crate::finalizer;
-use crate::namespace::{create_namespace, delete_namespace};
+use crate::layout::{Layout, ProjectLayout, ProjectVars};
+use crate::namespace::{
+    apply_namespace_metadata, create_namespace, delete_namespace, namespace_drift,
+    namespace_metadata, namespace_ownership, schedule_deletion, Ownership,
+};
+use crate::network_policy::{apply_network_policies, delete_network_policies};
+use crate::owner::{find_orphans, project_ref, ProjectOwner};
 use crate::project::{create_project, delete_project};
-use crate::project_crd::Project;
+use crate::project_crd::{GitOpsCommit, GitOpsStatus, Project, ProjectStatus};
+use crate::quota::{apply_quota, delete_quota, Quota};
 use crate::rbacs::{add_rbacs, remove_rbacs};
-use crate::secret::{create_secret, delete_secret};
-use crate::{Error, Gitlab, Metrics, Result};
+use crate::repository::KnownHosts;
+use crate::requeue::Requeue;
+use crate::rolebinding::{create_rolebinding, delete_rolebinding};
+use crate::scope::Scope;
+use crate::secret::{create_secret, delete_secret, PULL_SECRET_NAME};
+use crate::shutdown::Shutdown;
+use crate::templates::Templates;
+use crate::vault::{self, VaultBackend};
+use crate::vault_policy;
+use crate::{finalizer, status};
+use crate::{Error, GitCredentials, Gitlab, Metrics, Plan, Result};
+
+/// Annotation switching a single Project to dry-run mode
+pub const DRY_RUN_ANNOTATION: &str = "kyotu.tech/dry-run";
+
+/// Annotation blocking the deletion of a Project, on by default for prod
+pub const DELETION_PROTECTION_ANNOTATION: &str = "kyotu.tech/deletion-protection";
+
+/// Kubernetes caps Event notes at 1kB
+const MAX_EVENT_NOTE_LEN: usize = 1024;
 
 #[derive(Clone)]
 pub struct Context {
     pub client: Client,
     pub gitlab: Gitlab,
+    /// Credentials for the deployment (ArgoCD) repository
+    pub argo_credentials: GitCredentials,
+    /// Credentials for the Flux repository
+    pub flux_credentials: GitCredentials,
     /// Diagnostics read by the web server
     pub diagnostics: Arc<RwLock<Diagnostics>>,
     /// Prometheus metrics
     pub metrics: Metrics,
+    /// Only plan changes for every Project, never commit, push or touch GitLab and Kubernetes
+    pub dry_run: bool,
+    /// Dry-run plans read by the web server, keyed by `<namespace>/<name>`
+    pub plans: Arc<RwLock<BTreeMap<String, ProjectPlan>>>,
+    /// Paths written in the GitOps repositories
+    pub layout: Layout,
+    /// Templates rendered into the GitOps repositories, reloaded on change
+    pub templates: Templates,
+    /// Where Vault policies and groups are configured
+    pub vault: Arc<dyn VaultBackend>,
+    /// Time a namespace stays scaled down before it is deleted with its Project
+    pub deletion_grace_period: Duration,
+    /// Resync interval and backoff after errors
+    pub requeue: Requeue,
+    /// Set once shutdown is requested, no git transaction is started after that
+    pub shutdown: Shutdown,
 }
 
 enum ProjectAction {
     Create,
     Delete,
-    NoOp,
+    Plan,
+    /// Provisioned, only the namespace is kept in sync
+    Sync,
 }
 
 pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<Action> {
     let _timer = context.metrics.count_and_measure();
     context.diagnostics.write().await.last_event = Utc::now();
 
+    let action = reconcile_project(project.clone(), context.clone()).await?;
+    if context.requeue.succeeded(&requeue_key(&project)) {
+        context.metrics.reset_backoff(&project);
+    }
+    Ok(action)
+}
+
+//key of a project in the requeue backoff, `<namespace>/<name>`
+fn requeue_key(project: &Project) -> String {
+    format!(
+        "{}/{}",
+        project.namespace().unwrap_or_default(),
+        project.name_any()
+    )
+}
+
+//drop the backoff and its metrics once the finalizer of a deleted project is removed
+fn forget(project: &Project, context: &Context) {
+    context.requeue.forget(&requeue_key(project));
+    context.metrics.reset_backoff(project);
+}
+
+async fn reconcile_project(project: Arc<Project>, context: Arc<Context>) -> Result<Action> {
     let client = context.client.clone();
     let gitlab = context.gitlab.clone();
 
@@ -66,9 +139,55 @@ pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<A
         Some(namespace) => namespace,
     };
 
+    let project_vars = ProjectVars {
+        project_name: project_name.clone(),
+        project_id: project_id.clone(),
+        environment_type: environment_type.clone(),
+        namespace: namespace.clone(),
+        google_group,
+    };
+    let vault_spec = project.spec.vault.clone().unwrap_or_default();
+    let layout = context
+        .layout
+        .render(&project_vars)
+        .map_err(Error::LayoutError)?;
+    let owner = ProjectOwner::from_project(&project);
+
+    //provisioned projects missing their namespace or pull secret are provisioned again
+    let action = match determine_action(&project, context.dry_run) {
+        ProjectAction::Sync => match namespace_drift(client.clone(), &project_name)
+            .await
+            .map_err(|e| Error::NamespaceError(e.to_string()))?
+        {
+            Some(drift) => {
+                log::info!("{}, provisioning {} again", drift, project_name);
+                ProjectAction::Create
+            }
+            None => ProjectAction::Sync,
+        },
+        action => action,
+    };
+
+    //shutting down, git and gitlab changes are left to the next leader
+    if matches!(
+        action,
+        ProjectAction::Create | ProjectAction::Delete | ProjectAction::Plan
+    ) {
+        if let Some(requeue) = interrupted(&context.shutdown, &project_name, "reconciling") {
+            return Ok(requeue);
+        }
+    }
+
     #[allow(clippy::needless_return)]
-    return match determine_action(&project) {
+    return match action {
         ProjectAction::Create => {
+            //reject vault access the environment or backend does not allow before changing anything
+            vault_policy::check_paths(&environment_type, &vault_spec.paths)
+                .map_err(|e| Error::UserInputError(e.to_string()))?;
+            context
+                .vault
+                .check(&vault_spec)
+                .map_err(|e| Error::UserInputError(e.to_string()))?;
             let recorder = context
                 .diagnostics
                 .read()
@@ -83,13 +202,64 @@ pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<A
             .await
             .unwrap();
 
-            match create_namespace(client.clone(), &project_name).await {
+            match create_namespace(
+                client.clone(),
+                &project_name,
+                &owner,
+                project.spec.adopt_existing_namespace,
+            )
+            .await
+            {
+                Ok(Ownership::Conflict(conflict)) => {
+                    //leave the namespace and everything else alone until the conflict is resolved
+                    log::warn!("{}", conflict);
+                    status::patch(
+                        client.clone(),
+                        project.metadata.name.as_ref().unwrap(),
+                        &namespace,
+                        &ProjectStatus {
+                            conflict: Some(conflict.clone()),
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .map_err(Error::KubeError)?;
+                    recorder
+                        .publish(Event {
+                            type_: EventType::Warning,
+                            reason: "NamespaceConflict".into(),
+                            note: Some(conflict),
+                            action: "Creating".into(),
+                            secondary: None,
+                        })
+                        .await
+                        .map_err(Error::KubeError)?;
+                    return Ok(Action::requeue(Duration::from_secs(60)));
+                }
+                Ok(Ownership::Adopted) => {
+                    recorder
+                        .publish(Event {
+                            type_: EventType::Normal,
+                            reason: "NamespaceAdopted".into(),
+                            note: Some(format!("Adopted namespace `{project_name}`")),
+                            action: "Creating".into(),
+                            secondary: None,
+                        })
+                        .await
+                        .map_err(Error::KubeError)?;
+                }
                 Ok(_) => {}
                 Err(e) => {
                     log::error!("Failed to create namespace: {:?}", e);
                 }
             }
+            sync_namespace(client.clone(), &project, &project_vars, &owner).await?;
 
+            if let Some(requeue) =
+                interrupted(&context.shutdown, &project_name, "rotating the pull token")
+            {
+                return Ok(requeue);
+            }
             let group_id = gitlab.create_group(&project_id).await.unwrap();
 
             //check if pull token exists
@@ -115,13 +285,22 @@ pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<A
                         .await
                 }
             };
-            create_secret(client.clone(), &project_name, &pull_token.unwrap())
-                .await
-                .unwrap();
-            create_project(&project_name, argo_root).await.unwrap();
-            add_rbacs(&project_name, flux_root, &google_group)
+            create_secret(client.clone(), &project_name, &pull_token.unwrap(), &owner)
                 .await
                 .unwrap();
+            if let Some(requeue) = commit_gitops(
+                &project,
+                &context,
+                &project_vars,
+                &layout,
+                argo_root,
+                flux_root,
+                false,
+            )
+            .await?
+            {
+                return Ok(requeue);
+            }
 
             recorder
                 .publish(Event {
@@ -133,7 +312,7 @@ pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<A
                 })
                 .await
                 .map_err(Error::KubeError)?;
-            Ok(Action::requeue(Duration::from_secs(10)))
+            Ok(Action::requeue(context.requeue.resync()))
         }
         ProjectAction::Delete => {
             let recorder = context
@@ -141,22 +320,143 @@ pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<A
                 .read()
                 .await
                 .recorder(context.client.clone(), &project);
-            remove_rbacs(&project_name, flux_root, &google_group)
+            if deletion_protected(&project) {
+                recorder
+                    .publish(Event {
+                        type_: EventType::Warning,
+                        reason: "DeletionProtected".into(),
+                        note: Some(format!(
+                            "`{project_name}` is protected, set the {DELETION_PROTECTION_ANNOTATION} annotation to \"false\" to delete it"
+                        )),
+                        action: "Deleting".into(),
+                        secondary: None,
+                    })
+                    .await
+                    .map_err(Error::KubeError)?;
+                return Ok(Action::await_change());
+            }
+            //ownership is checked before any cleanup, a conflicting project never provisioned anything
+            let ownership = namespace_ownership(client.clone(), &project_name, &owner)
                 .await
-                .unwrap();
-            match delete_project(&project_name, argo_root).await {
-                Ok(_) => {}
+                .map_err(|e| Error::NamespaceError(e.to_string()))?;
+            if let Some(Ownership::Conflict(conflict)) = &ownership {
+                recorder
+                    .publish(Event {
+                        type_: EventType::Warning,
+                        reason: "NamespaceConflict".into(),
+                        note: Some(format!("{conflict}, skipping cleanup")),
+                        action: "Deleting".into(),
+                        secondary: None,
+                    })
+                    .await
+                    .map_err(Error::KubeError)?;
+                finalizer::delete(client, project.metadata.name.as_ref().unwrap(), &namespace)
+                    .await
+                    .map_err(Error::KubeError)?;
+                forget(&project, &context);
+                return Ok(Action::await_change());
+            }
+            if !context.deletion_grace_period.is_zero() {
+                let remaining = schedule_deletion(
+                    client.clone(),
+                    &project_name,
+                    &owner,
+                    context.deletion_grace_period,
+                )
+                .await
+                .map_err(|e| Error::NamespaceError(e.to_string()))?;
+                if let Some(remaining) = remaining {
+                    recorder
+                        .publish(Event {
+                            type_: EventType::Normal,
+                            reason: "PendingDeletion".into(),
+                            note: Some(format!(
+                                "Namespace `{project_name}` is scaled down and deleted in {}s",
+                                remaining.as_secs()
+                            )),
+                            action: "Deleting".into(),
+                            secondary: None,
+                        })
+                        .await
+                        .map_err(Error::KubeError)?;
+                    return Ok(Action::requeue(remaining));
+                }
+            }
+            if let Some(requeue) =
+                interrupted(&context.shutdown, &project_name, "revoking vault access")
+            {
+                return Ok(requeue);
+            }
+            let flux_commit = remove_rbacs(
+                &project_vars,
+                flux_root,
+                &layout,
+                context.vault.as_ref(),
+                &context.flux_credentials,
+                false,
+            )
+            .await
+            .map_err(|e| Error::GitOpsError(e.to_string()))?
+            .commit();
+            if let Some(event) = flux_commit.and_then(|commit| commit_event(&commit, "Removed")) {
+                recorder.publish(event).await.map_err(Error::KubeError)?;
+            }
+            if let Some(requeue) = interrupted(
+                &context.shutdown,
+                &project_name,
+                "removing the argo project",
+            ) {
+                return Ok(requeue);
+            }
+            match delete_project(
+                &project_name,
+                argo_root,
+                &layout,
+                &context.argo_credentials,
+                false,
+            )
+            .await
+            {
+                Ok(outcome) => {
+                    if let Some(event) = outcome
+                        .commit()
+                        .and_then(|commit| commit_event(&commit, "Removed"))
+                    {
+                        recorder.publish(event).await.map_err(Error::KubeError)?;
+                    }
+                }
                 Err(e) => {
                     log::error!("Failed to delete project: {:?}", e);
                 }
             }
-            delete_secret(client.clone(), &project_name).await.unwrap();
-            delete_namespace(client.clone(), &project_name)
-                .await
-                .unwrap();
+            //a namespace the project does not own is left as it is
+            if ownership == Some(Ownership::Owned) {
+                delete_secret(client.clone(), &project_name)
+                    .await
+                    .map_err(|e| Error::NamespaceError(e.to_string()))?;
+                if let Err(e) = delete_rolebinding(client.clone(), &project_name).await {
+                    log::error!("Failed to delete rolebinding: {:?}", e);
+                }
+                if let Err(e) = delete_quota(client.clone(), &project_name).await {
+                    log::error!("Failed to delete quota: {:?}", e);
+                }
+                if let Err(e) = delete_network_policies(client.clone(), &project_name).await {
+                    log::error!("Failed to delete network policies: {:?}", e);
+                }
+                delete_namespace(client.clone(), &project_name, &owner)
+                    .await
+                    .map_err(|e| Error::NamespaceError(e.to_string()))?;
+            } else {
+                log::warn!(
+                    "Namespace {} is not owned by Project {}, skipping its cleanup",
+                    project_name,
+                    owner.project()
+                );
+            }
             finalizer::delete(client, project.metadata.name.as_ref().unwrap(), &namespace)
                 .await
-                .unwrap();
+                .map_err(Error::KubeError)?;
+            forget(&project, &context);
 
             recorder
                 .publish(Event {
@@ -170,24 +470,398 @@ pub async fn reconcile(project: Arc<Project>, context: Arc<Context>) -> Result<A
                 .map_err(Error::KubeError)?;
             Ok(Action::await_change())
         }
-        ProjectAction::NoOp => Ok(Action::requeue(Duration::from_secs(10))),
+        ProjectAction::Plan => {
+            let recorder = context
+                .diagnostics
+                .read()
+                .await
+                .recorder(client.clone(), &project);
+            let deleting = project.meta().deletion_timestamp.is_some();
+
+            //edit the working trees and diff them, gitlab and kubernetes are left untouched
+            let (argo_plan, flux_plan) = if deleting {
+                let flux_plan = remove_rbacs(
+                    &project_vars,
+                    flux_root,
+                    &layout,
+                    context.vault.as_ref(),
+                    &context.flux_credentials,
+                    true,
+                )
+                .await
+                .map_err(|e| Error::GitOpsError(e.to_string()))?
+                .plan();
+                let argo_plan = match delete_project(
+                    &project_name,
+                    argo_root,
+                    &layout,
+                    &context.argo_credentials,
+                    true,
+                )
+                .await
+                {
+                    Ok(outcome) => outcome.plan(),
+                    Err(e) => {
+                        log::error!("Failed to plan project deletion: {:?}", e);
+                        None
+                    }
+                };
+                (argo_plan, flux_plan)
+            } else {
+                let argo_plan = create_project(
+                    &project_vars,
+                    argo_root,
+                    &layout,
+                    &context.templates,
+                    &context.argo_credentials,
+                    true,
+                )
+                .await
+                .map_err(|e| Error::GitOpsError(e.to_string()))?
+                .plan();
+                let flux_plan = add_rbacs(
+                    &project_vars,
+                    &vault_spec,
+                    flux_root,
+                    &layout,
+                    &context.templates,
+                    context.vault.as_ref(),
+                    &context.flux_credentials,
+                    true,
+                )
+                .await
+                .map_err(|e| Error::GitOpsError(e.to_string()))?
+                .plan();
+                (argo_plan, flux_plan)
+            };
+
+            let key = format!("{namespace}/{}", project.name_any());
+            for plan in argo_plan.iter().chain(flux_plan.iter()) {
+                recorder
+                    .publish(plan_event(plan, &key))
+                    .await
+                    .map_err(Error::KubeError)?;
+            }
+            context.plans.write().await.insert(
+                key,
+                ProjectPlan {
+                    planned_at: Utc::now(),
+                    deleting,
+                    argo: argo_plan,
+                    flux: flux_plan,
+                },
+            );
+            Ok(Action::await_change())
+        }
+        ProjectAction::Sync => {
+            sync_namespace(client, &project, &project_vars, &owner).await?;
+            //creates interrupted before all gitops commits were recorded are resumed, the pull token is left alone
+            if let Some(requeue) = commit_gitops(
+                &project,
+                &context,
+                &project_vars,
+                &layout,
+                argo_root,
+                flux_root,
+                true,
+            )
+            .await?
+            {
+                return Ok(requeue);
+            }
+            Ok(Action::requeue(context.requeue.resync()))
+        }
     };
 }
 
-pub async fn run(state: State) {
+//commit the argo project and the flux rbacs, recording each commit in the status once it is pushed,
+//when resuming only the commits missing from the status are made
+async fn commit_gitops(
+    project: &Project,
+    context: &Context,
+    project_vars: &ProjectVars,
+    layout: &ProjectLayout,
+    argo_root: &Path,
+    flux_root: &Path,
+    resume: bool,
+) -> Result<Option<Action>> {
+    let project_name = &project_vars.project_name;
+    let vault_spec = project.spec.vault.clone().unwrap_or_default();
+    let previous = project
+        .status
+        .as_ref()
+        .and_then(|status| status.gitops.clone())
+        .unwrap_or_default();
+    let recorder = context
+        .diagnostics
+        .read()
+        .await
+        .recorder(context.client.clone(), project);
+
+    if !resume || previous.argo.is_none() {
+        if let Some(requeue) = interrupted(
+            &context.shutdown,
+            project_name,
+            "committing the argo project",
+        ) {
+            return Ok(Some(requeue));
+        }
+        let argo_commit = create_project(
+            project_vars,
+            argo_root,
+            layout,
+            &context.templates,
+            &context.argo_credentials,
+            false,
+        )
+        .await
+        .map_err(|e| Error::GitOpsError(e.to_string()))?
+        .commit();
+        if let Some(event) = argo_commit
+            .as_ref()
+            .and_then(|commit| commit_event(commit, "Created"))
+        {
+            recorder.publish(event).await.map_err(Error::KubeError)?;
+        }
+        //keep the earlier sha when nothing changed
+        record_gitops(
+            project,
+            context,
+            GitOpsStatus {
+                argo: argo_commit.map(|commit| commit.or_previous(previous.argo.as_ref())),
+                flux: None,
+            },
+        )
+        .await?;
+    }
+
+    if !resume || previous.flux.is_none() {
+        //creates check the vault access before changing anything, resumes before granting it
+        if resume {
+            vault_policy::check_paths(&project_vars.environment_type, &vault_spec.paths)
+                .map_err(|e| Error::UserInputError(e.to_string()))?;
+            context
+                .vault
+                .check(&vault_spec)
+                .map_err(|e| Error::UserInputError(e.to_string()))?;
+        }
+        if let Some(requeue) = interrupted(&context.shutdown, project_name, "granting vault access")
+        {
+            return Ok(Some(requeue));
+        }
+        let flux_commit = add_rbacs(
+            project_vars,
+            &vault_spec,
+            flux_root,
+            layout,
+            &context.templates,
+            context.vault.as_ref(),
+            &context.flux_credentials,
+            false,
+        )
+        .await
+        .map_err(|e| Error::GitOpsError(e.to_string()))?
+        .commit();
+        if let Some(event) = flux_commit
+            .as_ref()
+            .and_then(|commit| commit_event(commit, "Created"))
+        {
+            recorder.publish(event).await.map_err(Error::KubeError)?;
+        }
+        record_gitops(
+            project,
+            context,
+            GitOpsStatus {
+                argo: None,
+                flux: flux_commit.map(|commit| commit.or_previous(previous.flux.as_ref())),
+            },
+        )
+        .await?;
+    }
+    Ok(None)
+}
+
+//merge gitops commits into the status, the ones left out are kept
+async fn record_gitops(project: &Project, context: &Context, gitops: GitOpsStatus) -> Result<()> {
+    status::patch(
+        context.client.clone(),
+        project.metadata.name.as_ref().unwrap(),
+        project.metadata.namespace.as_ref().unwrap(),
+        &ProjectStatus {
+            gitops: Some(gitops),
+            conflict: None,
+        },
+    )
+    .await
+    .map_err(Error::KubeError)?;
+    Ok(())
+}
+
+//apply labels, rolebinding, quota and network policies of a provisioned project to its namespace
+async fn sync_namespace(
+    client: Client,
+    project: &Project,
+    project_vars: &ProjectVars,
+    owner: &ProjectOwner,
+) -> Result<()> {
+    let project_name = &project_vars.project_name;
+    let environment_type = &project_vars.environment_type;
+    let (labels, annotations) =
+        namespace_metadata(project_vars, owner, project.spec.namespace.as_ref());
+    if let Err(e) =
+        apply_namespace_metadata(client.clone(), project_name, &labels, &annotations).await
+    {
+        log::error!("Failed to label namespace: {:?}", e);
+    }
+    if let Err(e) = create_rolebinding(
+        client.clone(),
+        project_name,
+        &project_vars.google_group,
+        environment_type,
+        owner,
+    )
+    .await
+    {
+        log::error!("Failed to create rolebinding: {:?}", e);
+    }
+    let quota = Quota::from_spec(project.spec.quota.as_ref(), environment_type)
+        .map_err(|e| Error::UserInputError(e.to_string()))?;
+    if let Err(e) = apply_quota(client.clone(), project_name, &quota, owner).await {
+        log::error!("Failed to apply quota: {:?}", e);
+    }
+    if let Err(e) = apply_network_policies(
+        client.clone(),
+        project_name,
+        project.spec.network.as_ref(),
+        owner,
+    )
+    .await
+    {
+        log::error!("Failed to apply network policies: {:?}", e);
+    }
+    Ok(())
+}
+
+pub async fn run(state: State, dry_run: bool) {
     let client = Client::try_default()
         .await
         .expect("Failed to create client");
 
-    let crd_api: Api<Project> = Api::all(client.clone());
-
     let gitlab_url = std::env::var("GITLAB_URL").expect("GITLAB_URL not set");
     let gitlab_token = std::env::var("GITLAB_TOKEN").expect("GITLAB_TOKEN not set");
 
     let gitlab = Gitlab::new(gitlab_url, gitlab_token);
 
-    Controller::new(crd_api.clone(), Config::default().any_semantic())
-        .run(reconcile, on_error, state.to_context(client, gitlab))
+    let operator_namespace =
+        std::env::var("OPERATOR_NAMESPACE").expect("OPERATOR_NAMESPACE not set");
+    let argo_secret =
+        std::env::var("ARGO_CREDENTIALS_SECRET").expect("ARGO_CREDENTIALS_SECRET not set");
+    let flux_secret =
+        std::env::var("FLUX_CREDENTIALS_SECRET").expect("FLUX_CREDENTIALS_SECRET not set");
+    let argo_credentials =
+        GitCredentials::from_secret(client.clone(), &operator_namespace, &argo_secret)
+            .await
+            .expect("Failed to load ArgoCD repository credentials");
+    let flux_credentials =
+        GitCredentials::from_secret(client.clone(), &operator_namespace, &flux_secret)
+            .await
+            .expect("Failed to load Flux repository credentials");
+
+    //fail fast on a known_hosts file that cannot be read or parsed
+    KnownHosts::from_env().expect("Failed to load known_hosts");
+
+    //fail fast when the configured layout does not match the repositories
+    let layout = Layout::from_env().expect("Failed to load repository layout");
+    let argo_root = std::env::var("ARGO_ROOT").unwrap_or("tmp/argo_repo".to_string());
+    let flux_root = std::env::var("FLUX_ROOT").unwrap_or("tmp/flux_repo".to_string());
+    layout
+        .validate_repositories(
+            Path::new(&argo_root),
+            Path::new(&flux_root),
+            &argo_credentials,
+            &flux_credentials,
+        )
+        .await
+        .expect("Repository layout does not match the GitOps repositories");
+
+    let templates = Templates::from_env();
+    let reload_interval = std::env::var("TEMPLATES_RELOAD_INTERVAL")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(10);
+    tokio::spawn(
+        templates
+            .clone()
+            .watch(Duration::from_secs(reload_interval)),
+    );
+
+    let vault = vault::from_env().expect("Failed to configure the Vault backend");
+    let deletion_grace_period = Duration::from_secs(
+        std::env::var("NAMESPACE_DELETION_GRACE_PERIOD")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(0),
+    );
+
+    let scope = Scope::from_env();
+    info!("Watching Projects in {:?}", scope);
+    let context = state.to_context(
+        client,
+        gitlab,
+        argo_credentials,
+        flux_credentials,
+        dry_run,
+        layout,
+        templates,
+        vault,
+        deletion_grace_period,
+        Requeue::from_env(),
+    );
+    let sweep_interval = std::env::var("ORPHAN_SWEEP_INTERVAL")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(300);
+    if sweep_interval > 0 {
+        tokio::spawn(sweep_orphans(
+            context.clone(),
+            scope.clone(),
+            Duration::from_secs(sweep_interval),
+        ));
+    }
+
+    //a single controller for every watched namespace, its store only holds Projects in scope
+    let (projects, writer) = reflector::store::<Project>();
+    let project_events = reflector(
+        writer,
+        scope.project_events(context.client.clone(), projects.clone()),
+    )
+    .applied_objects();
+    //namespaces and secrets changed outside of the operator trigger their Project right away
+    let store = projects.clone();
+    let in_scope = move |project: Option<ObjectRef<Project>>| {
+        project.filter(|project| store.get(project).is_some())
+    };
+    let secret_in_scope = in_scope.clone();
+    let namespaces = watcher(
+        Api::<Namespace>::all(context.client.clone()),
+        scope.owned_config(),
+    )
+    .touched_objects();
+    //only the pull secret is watched, so the operator needs no list access to other secrets
+    let secrets = watcher(
+        Api::<Secret>::all(context.client.clone()),
+        scope
+            .owned_config()
+            .fields(&format!("metadata.name={PULL_SECRET_NAME}")),
+    )
+    .touched_objects();
+    Controller::for_stream(project_events, projects)
+        .watches_stream(namespaces, move |ns| in_scope(project_ref(&ns.metadata)))
+        .watches_stream(secrets, move |secret| {
+            secret_in_scope(project_ref(&secret.metadata))
+        })
+        .graceful_shutdown_on(context.shutdown.triggered())
+        .run(reconcile, on_error, context.clone())
         .for_each(|reconciliation_result| async move {
             match reconciliation_result {
                 Ok(echo_resource) => {
@@ -199,18 +873,80 @@ pub async fn run(state: State) {
             }
         })
         .await;
+    info!("Controller drained");
+}
+
+//periodically report objects labelled for Projects that no longer exist
+async fn sweep_orphans(context: Arc<Context>, scope: Scope, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match find_orphans(context.client.clone(), &scope.namespaces).await {
+            Ok(orphans) => {
+                for orphan in &orphans {
+                    log::warn!(
+                        "{} {}{} was created for Project {} which no longer exists",
+                        orphan.kind,
+                        orphan
+                            .namespace
+                            .as_ref()
+                            .map(|namespace| format!("{namespace}/"))
+                            .unwrap_or_default(),
+                        orphan.name,
+                        orphan.project
+                    );
+                }
+                context.metrics.set_orphans(&orphans);
+            }
+            Err(e) => log::error!("Failed to sweep orphaned objects: {:?}", e),
+        }
+    }
+}
+
+//requeue instead of starting a git, gitlab or vault transaction once shutdown is requested
+fn interrupted(shutdown: &Shutdown, project_name: &str, transaction: &str) -> Option<Action> {
+    if !shutdown.requested() {
+        return None;
+    }
+    log::info!("Shutting down, not {} for {}", transaction, project_name);
+    Some(Action::requeue(Duration::from_secs(5)))
+}
+
+//explicit annotation, or protected by default for prod
+fn deletion_protected(project: &Project) -> bool {
+    match project
+        .annotations()
+        .get(DELETION_PROTECTION_ANNOTATION)
+        .map(String::as_str)
+    {
+        Some("true") => true,
+        Some("false") => false,
+        _ => project.spec.environment_type == "prod",
+    }
 }
 
 #[allow(clippy::needless_return)]
 //determine action to take based on the state of the echo CRD
-fn determine_action(project: &Project) -> ProjectAction {
-    return if project.meta().deletion_timestamp.is_some() {
+fn determine_action(project: &Project, dry_run: bool) -> ProjectAction {
+    let dry_run = dry_run
+        || project
+            .annotations()
+            .get(DRY_RUN_ANNOTATION)
+            .is_some_and(|value| value == "true");
+    return if dry_run {
+        ProjectAction::Plan
+    } else if project.meta().deletion_timestamp.is_some() {
         ProjectAction::Delete
     } else if project
         .meta()
         .finalizers
         .as_ref()
         .is_none_or(|finalizers| finalizers.is_empty())
+        //namespace conflicts are retried until the project can be provisioned
+        || project
+            .status
+            .as_ref()
+            .is_some_and(|status| status.conflict.is_some())
     {
         log::info!(
             "Project {} {} is being created {}",
@@ -220,15 +956,61 @@ fn determine_action(project: &Project) -> ProjectAction {
         );
         ProjectAction::Create
     } else {
-        ProjectAction::NoOp
+        ProjectAction::Sync
     };
 }
 
-//error handling
+//event referencing a gitops commit, none when nothing was committed
+fn commit_event(commit: &GitOpsCommit, verb: &str) -> Option<Event> {
+    let sha = commit.commit_sha.as_ref()?;
+    Some(Event {
+        type_: EventType::Normal,
+        reason: "Committed".into(),
+        note: Some(format!(
+            "{verb} `{}` in {} ({}) with commit {sha}",
+            commit.path, commit.repo, commit.branch
+        )),
+        action: "Committing".into(),
+        secondary: None,
+    })
+}
+
+//event carrying a dry-run diff, truncated to fit the event note
+fn plan_event(plan: &Plan, key: &str) -> Event {
+    let note = if plan.diff.is_empty() {
+        format!("No changes planned for {} ({})", plan.repo, plan.branch)
+    } else {
+        let note = format!(
+            "Planned changes for {} ({}):\n{}",
+            plan.repo, plan.branch, plan.diff
+        );
+        let suffix = format!("\n... truncated, see /plans/{key}");
+        if note.len() > MAX_EVENT_NOTE_LEN {
+            let mut end = MAX_EVENT_NOTE_LEN - suffix.len();
+            while !note.is_char_boundary(end) {
+                end -= 1;
+            }
+            format!("{}{suffix}", &note[..end])
+        } else {
+            note
+        }
+    };
+    Event {
+        type_: EventType::Normal,
+        reason: "Planned".into(),
+        note: Some(note),
+        action: "Planning".into(),
+        secondary: None,
+    }
+}
+
+//error handling, retried with a backoff growing with the errors in a row
 pub fn on_error(proj: Arc<Project>, error: &Error, context: Arc<Context>) -> Action {
     eprintln!("Reconciliation error:\n{error:?}.\n{proj:?}");
     context.metrics.reconcile_failure(&proj, error);
-    Action::requeue(Duration::from_secs(5))
+    let (errors, delay) = context.requeue.failed(&requeue_key(&proj));
+    context.metrics.backoff(&proj, errors, delay);
+    Action::requeue(delay)
 }
 
 /// State shared between the controller and the web server
@@ -238,6 +1020,10 @@ pub struct State {
     diagnostics: Arc<RwLock<Diagnostics>>,
     /// Metrics registry
     registry: prometheus::Registry,
+    /// Dry-run plans populated by the reconciler
+    plans: Arc<RwLock<BTreeMap<String, ProjectPlan>>>,
+    /// Shutdown requested by a signal
+    shutdown: Shutdown,
 }
 
 /// State wrapper around the controller outputs for the web server
@@ -252,22 +1038,72 @@ impl State {
         self.diagnostics.read().await.clone()
     }
 
+    /// Shutdown getter
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Record the replica holding the leader election Lease
+    pub async fn set_leader(&self, leader: Option<String>) {
+        self.diagnostics.write().await.leader = leader;
+    }
+
+    /// Dry-run plans getter
+    pub async fn plans(&self) -> BTreeMap<String, ProjectPlan> {
+        self.plans.read().await.clone()
+    }
+
     // Create a Controller Context that can update State
-    pub fn to_context(&self, client: Client, gitlab: Gitlab) -> Arc<Context> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn to_context(
+        &self,
+        client: Client,
+        gitlab: Gitlab,
+        argo_credentials: GitCredentials,
+        flux_credentials: GitCredentials,
+        dry_run: bool,
+        layout: Layout,
+        templates: Templates,
+        vault: Arc<dyn VaultBackend>,
+        deletion_grace_period: Duration,
+        requeue: Requeue,
+    ) -> Arc<Context> {
         Arc::new(Context {
             client,
             gitlab,
+            argo_credentials,
+            flux_credentials,
             metrics: Metrics::default().register(&self.registry).unwrap(),
             diagnostics: self.diagnostics.clone(),
+            dry_run,
+            plans: self.plans.clone(),
+            layout,
+            templates,
+            vault,
+            deletion_grace_period,
+            requeue,
+            shutdown: self.shutdown.clone(),
         })
     }
 }
 
+/// Diffs the operator would push for a Project in dry-run mode
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectPlan {
+    pub planned_at: DateTime<Utc>,
+    pub deleting: bool,
+    pub argo: Option<Plan>,
+    pub flux: Option<Plan>,
+}
+
 /// Diagnostics to be exposed by the web server
 #[derive(Clone, Serialize)]
 pub struct Diagnostics {
     #[serde(deserialize_with = "from_ts")]
     pub last_event: DateTime<Utc>,
+    /// Identity of the replica holding the leader election Lease, none without leader election
+    pub leader: Option<String>,
     #[serde(skip)]
     pub reporter: Reporter,
 }
@@ -275,6 +1111,7 @@ impl Default for Diagnostics {
     fn default() -> Self {
         Self {
             last_event: Utc::now(),
+            leader: None,
             reporter: Reporter {
                 controller: "kyotu-project-operator".into(),
                 instance: "default".to_string().into(),
@@ -287,3 +1124,90 @@ impl Diagnostics {
         Recorder::new(client, self.reporter.clone(), proj.object_ref(&()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_event_truncates_diff() {
+        let plan = Plan {
+            repo: "git@github.com:Kyotu-Technology/flux.git".to_string(),
+            branch: "main".to_string(),
+            diff: "+ żółć\n".repeat(200),
+        };
+        let note = plan_event(&plan, "default/test").note.unwrap();
+        assert!(note.len() <= MAX_EVENT_NOTE_LEN);
+        assert!(note.ends_with("see /plans/default/test"));
+
+        let empty = Plan {
+            diff: String::new(),
+            ..plan
+        };
+        let note = plan_event(&empty, "default/test").note.unwrap();
+        assert!(note.starts_with("No changes planned"));
+    }
+
+    #[test]
+    fn test_shutdown_interrupts_reconciles_between_transactions() {
+        let shutdown = Shutdown::default();
+        //a reconcile started before shutdown runs its first transaction
+        assert_eq!(interrupted(&shutdown, "test-dev", "committing"), None);
+        shutdown.trigger();
+        //and requeues instead of starting the next one
+        assert_eq!(
+            interrupted(&shutdown, "test-dev", "granting vault access"),
+            Some(Action::requeue(Duration::from_secs(5)))
+        );
+
+        //the requeued reconcile resumes the missing commits without creating the project again
+        let mut project = Project::new(
+            "test",
+            crate::project_crd::ProjectSpec {
+                project_id: "test".to_string(),
+                environment_type: "dev".to_string(),
+                google_group: "test@kyotu.tech".to_string(),
+                vault: None,
+                quota: None,
+                network: None,
+                namespace: None,
+                adopt_existing_namespace: false,
+            },
+        );
+        project.meta_mut().finalizers = Some(vec!["project.kyotu.tech/finalizer".to_string()]);
+        assert!(matches!(
+            determine_action(&project, false),
+            ProjectAction::Sync
+        ));
+    }
+
+    #[test]
+    fn test_deletion_protection() {
+        let project = |environment_type: &str, protection: Option<&str>| {
+            let mut project = Project::new(
+                "test",
+                crate::project_crd::ProjectSpec {
+                    project_id: "test".to_string(),
+                    environment_type: environment_type.to_string(),
+                    google_group: "test@kyotu.tech".to_string(),
+                    vault: None,
+                    quota: None,
+                    network: None,
+                    namespace: None,
+                    adopt_existing_namespace: false,
+                },
+            );
+            if let Some(protection) = protection {
+                project.annotations_mut().insert(
+                    DELETION_PROTECTION_ANNOTATION.to_string(),
+                    protection.to_string(),
+                );
+            }
+            project
+        };
+        assert!(deletion_protected(&project("prod", None)));
+        assert!(!deletion_protected(&project("prod", Some("false"))));
+        assert!(!deletion_protected(&project("dev", None)));
+        assert!(deletion_protected(&project("dev", Some("true"))));
+    }
+}
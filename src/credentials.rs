@@ -0,0 +1,416 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::ByteString;
+use kube::{Api, Client};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Installation tokens are minted again when they are this close to expiry
+const TOKEN_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+/// Credentials used to clone and push a GitOps repository
+///
+/// Loaded from a Secret using the same keys as ArgoCD repository secrets:
+/// `sshPrivateKey` (+ `sshPassphrase`), `username` + `password`, `bearerToken`,
+/// or `githubAppID` + `githubAppInstallationID` + `githubAppPrivateKey`
+/// (+ `githubAppEnterpriseBaseUrl`).
+#[derive(Clone)]
+pub enum GitCredentials {
+    SshKey {
+        private_key: String,
+        passphrase: Option<String>,
+    },
+    HttpsBasic {
+        username: String,
+        password: String,
+    },
+    BearerToken {
+        token: String,
+    },
+    GitHubApp(GitHubApp),
+}
+
+impl std::fmt::Debug for GitCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitCredentials::SshKey { .. } => f.debug_struct("SshKey").finish(),
+            GitCredentials::HttpsBasic { username, .. } => f
+                .debug_struct("HttpsBasic")
+                .field("username", username)
+                .finish(),
+            GitCredentials::BearerToken { .. } => f.debug_struct("BearerToken").finish(),
+            GitCredentials::GitHubApp(app) => app.fmt(f),
+        }
+    }
+}
+
+impl GitCredentials {
+    //load credentials from a secret
+    pub async fn from_secret(
+        client: Client,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Self, CredentialsError> {
+        let secret_api: Api<Secret> = Api::namespaced(client, namespace);
+        let secret = secret_api.get(name).await.map_err(|e| {
+            CredentialsError::SecretError(format!("Could not get secret {namespace}/{name}: {e}"))
+        })?;
+        Self::from_secret_data(&secret.data.unwrap_or_default())
+    }
+
+    //build credentials from secret data, github app takes precedence over ssh, bearer and basic
+    pub fn from_secret_data(data: &BTreeMap<String, ByteString>) -> Result<Self, CredentialsError> {
+        let get = |key: &str| -> Result<Option<String>, CredentialsError> {
+            data.get(key)
+                .map(|value| {
+                    String::from_utf8(value.0.clone()).map_err(|_| {
+                        CredentialsError::SecretError(format!(
+                            "Secret key {key} is not valid UTF-8"
+                        ))
+                    })
+                })
+                .transpose()
+        };
+
+        if let Some(private_key) = get("githubAppPrivateKey")? {
+            let app_id = get("githubAppID")?.ok_or_else(|| missing("githubAppID"))?;
+            let installation_id = get("githubAppInstallationID")?
+                .ok_or_else(|| missing("githubAppInstallationID"))?;
+            let api_url = get("githubAppEnterpriseBaseUrl")?
+                .unwrap_or_else(|| "https://api.github.com".to_string());
+            return Ok(GitCredentials::GitHubApp(GitHubApp::new(
+                app_id,
+                installation_id,
+                private_key,
+                api_url,
+            )));
+        }
+        if let Some(private_key) = get("sshPrivateKey")? {
+            return Ok(GitCredentials::SshKey {
+                private_key,
+                passphrase: get("sshPassphrase")?,
+            });
+        }
+        if let Some(token) = get("bearerToken")? {
+            return Ok(GitCredentials::BearerToken { token });
+        }
+        if let Some(password) = get("password")? {
+            let username = get("username")?.ok_or_else(|| missing("username"))?;
+            return Ok(GitCredentials::HttpsBasic { username, password });
+        }
+        Err(CredentialsError::SecretError(
+            "Secret does not contain any supported git credentials".to_string(),
+        ))
+    }
+
+    //make sure short lived credentials are valid before a git transaction
+    pub async fn refresh(&self) -> Result<(), CredentialsError> {
+        if let GitCredentials::GitHubApp(app) = self {
+            app.token().await?;
+        }
+        Ok(())
+    }
+
+    //credential handed to libgit2
+    pub(crate) fn cred(&self, username_from_url: Option<&str>) -> Result<git2::Cred, git2::Error> {
+        match self {
+            GitCredentials::SshKey {
+                private_key,
+                passphrase,
+            } => git2::Cred::ssh_key_from_memory(
+                username_from_url.unwrap_or("git"),
+                None,
+                private_key,
+                passphrase.as_deref(),
+            ),
+            GitCredentials::HttpsBasic { username, password } => {
+                git2::Cred::userpass_plaintext(username, password)
+            }
+            GitCredentials::BearerToken { .. } => {
+                Err(git2::Error::from_str("Remote rejected the bearer token"))
+            }
+            GitCredentials::GitHubApp(app) => match app.cached_token() {
+                Some(token) => git2::Cred::userpass_plaintext("x-access-token", &token),
+                None => Err(git2::Error::from_str(
+                    "GitHub App installation token has not been minted",
+                )),
+            },
+        }
+    }
+
+    //extra http headers sent with every request
+    pub(crate) fn headers(&self) -> Vec<String> {
+        match self {
+            GitCredentials::BearerToken { token } => vec![format!("Authorization: Bearer {token}")],
+            _ => vec![],
+        }
+    }
+}
+
+fn missing(key: &str) -> CredentialsError {
+    CredentialsError::SecretError(format!("Secret is missing key {key}"))
+}
+
+/// GitHub App installation, mints and caches installation access tokens
+#[derive(Clone)]
+pub struct GitHubApp {
+    pub app_id: String,
+    pub installation_id: String,
+    pub api_url: String,
+    private_key: String,
+    client: reqwest::Client,
+    token: Arc<Mutex<Option<InstallationToken>>>,
+}
+
+impl std::fmt::Debug for GitHubApp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GitHubApp")
+            .field("app_id", &self.app_id)
+            .field("installation_id", &self.installation_id)
+            .field("api_url", &self.api_url)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+impl GitHubApp {
+    pub fn new(
+        app_id: String,
+        installation_id: String,
+        private_key: String,
+        api_url: String,
+    ) -> Self {
+        Self {
+            app_id,
+            installation_id,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            private_key,
+            client: reqwest::Client::new(),
+            token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    //installation token, minted again when missing or close to expiry
+    pub async fn token(&self) -> Result<String, CredentialsError> {
+        if let Some(token) = self.cached_token() {
+            return Ok(token);
+        }
+
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            self.api_url, self.installation_id
+        );
+        let res = self
+            .client
+            .post(&url)
+            .bearer_auth(self.jwt()?)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "kyotu-project-operator")
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| CredentialsError::GitHubAppError(e.to_string()))?;
+        let token: InstallationToken = res
+            .json()
+            .await
+            .map_err(|e| CredentialsError::GitHubAppError(e.to_string()))?;
+        log::info!(
+            "Minted GitHub App installation token for installation {}, expires at {}",
+            self.installation_id,
+            token.expires_at
+        );
+
+        let value = token.token.clone();
+        *self.token.lock().unwrap() = Some(token);
+        Ok(value)
+    }
+
+    fn cached_token(&self) -> Option<String> {
+        let refresh_at = Utc::now() + Duration::minutes(TOKEN_REFRESH_MARGIN_MINUTES);
+        self.token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|token| token.expires_at > refresh_at)
+            .map(|token| token.token.clone())
+    }
+
+    //app jwt, backdated to allow for clock drift
+    fn jwt(&self) -> Result<String, CredentialsError> {
+        let now = Utc::now();
+        let claims = Claims {
+            iat: (now - Duration::seconds(60)).timestamp(),
+            exp: (now + Duration::minutes(9)).timestamp(),
+            iss: self.app_id.clone(),
+        };
+        let key = EncodingKey::from_rsa_pem(self.private_key.as_bytes())
+            .map_err(|e| CredentialsError::GitHubAppError(format!("Invalid private key: {e}")))?;
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+            .map_err(|e| CredentialsError::GitHubAppError(e.to_string()))
+    }
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum CredentialsError {
+    #[error("Invalid credentials secret: {0}")]
+    SecretError(String),
+    #[error("Could not mint GitHub App token: {0}")]
+    GitHubAppError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_PRIVATE_KEY: &str = include_str!("../tests/fixtures/github_app_key.pem");
+
+    fn secret_data(entries: &[(&str, &str)]) -> BTreeMap<String, ByteString> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_secret_data() {
+        let ssh = GitCredentials::from_secret_data(&secret_data(&[
+            ("sshPrivateKey", "key"),
+            ("sshPassphrase", "secret"),
+        ]))
+        .unwrap();
+        assert!(matches!(
+            ssh,
+            GitCredentials::SshKey { passphrase: Some(ref p), .. } if p == "secret"
+        ));
+
+        let basic = GitCredentials::from_secret_data(&secret_data(&[
+            ("username", "operator"),
+            ("password", "token"),
+        ]))
+        .unwrap();
+        assert!(matches!(
+            basic,
+            GitCredentials::HttpsBasic { ref username, .. } if username == "operator"
+        ));
+
+        let bearer =
+            GitCredentials::from_secret_data(&secret_data(&[("bearerToken", "token")])).unwrap();
+        assert_eq!(bearer.headers(), vec!["Authorization: Bearer token"]);
+
+        let app = GitCredentials::from_secret_data(&secret_data(&[
+            ("githubAppID", "1"),
+            ("githubAppInstallationID", "2"),
+            ("githubAppPrivateKey", APP_PRIVATE_KEY),
+            ("sshPrivateKey", "ignored"),
+        ]))
+        .unwrap();
+        assert!(matches!(
+            app,
+            GitCredentials::GitHubApp(ref app) if app.api_url == "https://api.github.com"
+        ));
+    }
+
+    #[test]
+    fn test_from_secret_data_missing_keys() {
+        assert!(GitCredentials::from_secret_data(&secret_data(&[])).is_err());
+        assert!(GitCredentials::from_secret_data(&secret_data(&[("password", "token")])).is_err());
+        assert!(GitCredentials::from_secret_data(&secret_data(&[
+            ("githubAppPrivateKey", APP_PRIVATE_KEY),
+            ("githubAppID", "1"),
+        ]))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_github_app_token_is_cached() {
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+
+        let expires_at = (Utc::now() + Duration::hours(1)).to_rfc3339();
+        let mock = server
+            .mock("POST", "/app/installations/2/access_tokens")
+            .match_header("authorization", mockito::Matcher::Regex("^Bearer ".into()))
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{"token": "ghs_test", "expires_at": "{expires_at}"}}"#
+            ))
+            .expect(1)
+            .create();
+
+        let app = GitHubApp::new(
+            "1".to_string(),
+            "2".to_string(),
+            APP_PRIVATE_KEY.to_string(),
+            format!("http://{host}"),
+        );
+        assert_eq!(app.token().await.unwrap(), "ghs_test");
+        assert_eq!(app.token().await.unwrap(), "ghs_test");
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_github_app_token_is_refreshed_before_expiry() {
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+
+        let expires_at = (Utc::now() + Duration::minutes(2)).to_rfc3339();
+        let mock = server
+            .mock("POST", "/app/installations/2/access_tokens")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(format!(
+                r#"{{"token": "ghs_test", "expires_at": "{expires_at}"}}"#
+            ))
+            .expect(2)
+            .create();
+
+        let credentials = GitCredentials::GitHubApp(GitHubApp::new(
+            "1".to_string(),
+            "2".to_string(),
+            APP_PRIVATE_KEY.to_string(),
+            format!("http://{host}"),
+        ));
+        assert!(credentials.cred(None).is_err());
+        credentials.refresh().await.unwrap();
+        credentials.refresh().await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_github_app_token_error() {
+        let mut server = mockito::Server::new_async().await;
+        let host = server.host_with_port();
+
+        server
+            .mock("POST", "/app/installations/2/access_tokens")
+            .with_status(401)
+            .create();
+
+        let app = GitHubApp::new(
+            "1".to_string(),
+            "2".to_string(),
+            APP_PRIVATE_KEY.to_string(),
+            format!("http://{host}"),
+        );
+        assert!(matches!(
+            app.token().await,
+            Err(CredentialsError::GitHubAppError(_))
+        ));
+    }
+}
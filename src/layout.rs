@@ -0,0 +1,326 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use tera::{Context, Tera};
+
+use crate::credentials::GitCredentials;
+use crate::repository::{KnownHosts, Repository};
+
+/// Where the operator writes each artifact in the GitOps repositories
+///
+/// Every path is a Tera template relative to the repository root, rendered with
+/// `project_name`, `project_id`, `environment_type`, `namespace` and `google_group`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Layout {
+    #[serde(default)]
+    pub argo: ArgoLayout,
+    #[serde(default)]
+    pub flux: FluxLayout,
+}
+
+/// Layout of the deployment (ArgoCD) repository
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArgoLayout {
+    /// Directory holding the project manifests, created with a `.gitkeep`
+    pub project_dir: String,
+    /// AppProject and ApplicationSets rendered from `argo_tmpl.yaml`
+    pub application: String,
+}
+
+/// Layout of the Flux repository
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FluxLayout {
+    /// Vault Helm values holding `vault.externalConfig`
+    pub vault_values: String,
+    /// ArgoCD RBAC policy
+    pub argo_rbac: String,
+}
+
+impl Default for ArgoLayout {
+    fn default() -> Self {
+        Self {
+            project_dir: "manifests/{{ project_name }}".to_string(),
+            application: "applications/{{ project_name }}.yaml".to_string(),
+        }
+    }
+}
+
+impl Default for FluxLayout {
+    fn default() -> Self {
+        Self {
+            vault_values: "namespaces/vault/vault/rbac_values.yaml".to_string(),
+            argo_rbac: "namespaces/argocd/argocd-operator/rbac.yaml".to_string(),
+        }
+    }
+}
+
+/// Project variables available to the layout and GitOps templates
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectVars {
+    pub project_name: String,
+    pub project_id: String,
+    pub environment_type: String,
+    pub namespace: String,
+    pub google_group: String,
+}
+
+/// Layout rendered for a single project
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectLayout {
+    pub project_dir: PathBuf,
+    pub application: PathBuf,
+    pub vault_values: PathBuf,
+    pub argo_rbac: PathBuf,
+}
+
+impl Layout {
+    //load layout from the file pointed to by LAYOUT_CONFIG, default layout otherwise
+    pub fn from_env() -> Result<Self, LayoutError> {
+        match std::env::var("LAYOUT_CONFIG") {
+            Ok(path) => {
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|e| LayoutError::ConfigError(format!("Could not read {path}: {e}")))?;
+                serde_yaml::from_str(&contents)
+                    .map_err(|e| LayoutError::ConfigError(format!("Could not parse {path}: {e}")))
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    //render all paths for a project
+    pub fn render(&self, vars: &ProjectVars) -> Result<ProjectLayout, LayoutError> {
+        let context =
+            Context::from_serialize(vars).map_err(|e| LayoutError::TemplateError(e.to_string()))?;
+        Ok(ProjectLayout {
+            project_dir: render_path(&self.argo.project_dir, &context)?,
+            application: render_path(&self.argo.application, &context)?,
+            vault_values: render_path(&self.flux.vault_values, &context)?,
+            argo_rbac: render_path(&self.flux.argo_rbac, &context)?,
+        })
+    }
+
+    //check the templates render and point into the deployment repository checkout
+    pub fn validate_argo(&self, repo_root: &Path) -> Result<(), LayoutError> {
+        self.render(&ProjectVars::sample())?;
+        check_static_prefix(repo_root, &self.argo.project_dir)?;
+        check_static_prefix(repo_root, &self.argo.application)
+    }
+
+    //check the templates render and the edited files exist in the flux repository checkout
+    pub fn validate_flux(&self, repo_root: &Path) -> Result<(), LayoutError> {
+        self.render(&ProjectVars::sample())?;
+        check_edited_file(repo_root, &self.flux.vault_values)?;
+        check_edited_file(repo_root, &self.flux.argo_rbac)
+    }
+
+    //clone both repositories and validate the layout against them
+    pub async fn validate_repositories(
+        &self,
+        argo_root: &Path,
+        flux_root: &Path,
+        argo_credentials: &GitCredentials,
+        flux_credentials: &GitCredentials,
+    ) -> anyhow::Result<()> {
+        let repo_branch = std::env::var("REPO_BRANCH")?;
+        for (repo_var, repo_root, credentials) in [
+            ("ARGO_REPO", argo_root, argo_credentials),
+            ("FLUX_REPO", flux_root, flux_credentials),
+        ] {
+            let repo_url = std::env::var(repo_var)?;
+            if repo_root.exists() {
+                std::fs::remove_dir_all(repo_root)?;
+            }
+            credentials.refresh().await?;
+            Repository::clone(
+                &repo_url,
+                &repo_branch,
+                &repo_root.to_string_lossy(),
+                credentials,
+                KnownHosts::from_env()?,
+            )?;
+        }
+        self.validate_argo(argo_root)?;
+        self.validate_flux(flux_root)?;
+        log::info!("Repository layout is valid");
+        Ok(())
+    }
+}
+
+impl ProjectVars {
+    fn sample() -> Self {
+        Self {
+            project_name: "layout-check-dev".to_string(),
+            project_id: "layout-check".to_string(),
+            environment_type: "dev".to_string(),
+            namespace: "default".to_string(),
+            google_group: "layout-check@example.com".to_string(),
+        }
+    }
+}
+
+//render a path template, rejecting anything that escapes the repository
+fn render_path(template: &str, context: &Context) -> Result<PathBuf, LayoutError> {
+    let rendered = Tera::one_off(template, context, false)
+        .map_err(|e| LayoutError::TemplateError(format!("{template}: {e:?}")))?;
+    let path = PathBuf::from(rendered.trim());
+    let escapes = path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if path.as_os_str().is_empty() || escapes {
+        return Err(LayoutError::TemplateError(format!(
+            "{template} renders to {}, which is not inside the repository",
+            path.to_string_lossy()
+        )));
+    }
+    Ok(path)
+}
+
+//directory made of the path components before the first template expression
+fn static_prefix(template: &str) -> PathBuf {
+    let mut components: Vec<&str> = template
+        .split('/')
+        .take_while(|component| !component.contains("{{") && !component.contains("{%"))
+        .collect();
+    if components.len() == template.split('/').count() {
+        components.pop();
+    }
+    components.iter().collect()
+}
+
+fn check_static_prefix(repo_root: &Path, template: &str) -> Result<(), LayoutError> {
+    let prefix = static_prefix(template);
+    if !repo_root.join(&prefix).is_dir() {
+        return Err(LayoutError::MissingPath(format!(
+            "Directory {} for {template} does not exist",
+            prefix.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
+
+//files edited in place must exist, templated ones only need their static directory
+fn check_edited_file(repo_root: &Path, template: &str) -> Result<(), LayoutError> {
+    if template.contains("{{") || template.contains("{%") {
+        return check_static_prefix(repo_root, template);
+    }
+    if !repo_root.join(template).is_file() {
+        return Err(LayoutError::MissingPath(format!(
+            "File {template} does not exist"
+        )));
+    }
+    Ok(())
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    #[error("Invalid layout config: {0}")]
+    ConfigError(String),
+    #[error("Invalid layout template: {0}")]
+    TemplateError(String),
+    #[error("Layout does not match repository: {0}")]
+    MissingPath(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_default_layout() {
+        let layout = Layout::default().render(&ProjectVars::sample()).unwrap();
+        assert_eq!(
+            layout.project_dir,
+            PathBuf::from("manifests/layout-check-dev")
+        );
+        assert_eq!(
+            layout.application,
+            PathBuf::from("applications/layout-check-dev.yaml")
+        );
+        assert_eq!(
+            layout.vault_values,
+            PathBuf::from("namespaces/vault/vault/rbac_values.yaml")
+        );
+    }
+
+    #[test]
+    fn test_render_custom_layout() {
+        let layout: Layout = serde_yaml::from_str(
+            "argo:\n  projectDir: tenants/{{ environment_type }}/{{ project_id }}\nflux:\n  argoRbac: clusters/{{ environment_type }}/argocd/rbac.yaml\n",
+        )
+        .unwrap();
+        let rendered = layout.render(&ProjectVars::sample()).unwrap();
+        assert_eq!(
+            rendered.project_dir,
+            PathBuf::from("tenants/dev/layout-check")
+        );
+        assert_eq!(
+            rendered.application,
+            PathBuf::from("applications/layout-check-dev.yaml")
+        );
+        assert_eq!(
+            rendered.argo_rbac,
+            PathBuf::from("clusters/dev/argocd/rbac.yaml")
+        );
+    }
+
+    #[test]
+    fn test_render_rejects_escaping_paths() {
+        let vars = ProjectVars {
+            project_name: "../../etc".to_string(),
+            ..ProjectVars::sample()
+        };
+        assert!(matches!(
+            Layout::default().render(&vars),
+            Err(LayoutError::TemplateError(_))
+        ));
+    }
+
+    #[test]
+    fn test_static_prefix() {
+        assert_eq!(
+            static_prefix("manifests/{{ project_name }}"),
+            PathBuf::from("manifests")
+        );
+        assert_eq!(
+            static_prefix("applications/{{ project_name }}.yaml"),
+            PathBuf::from("applications")
+        );
+        assert_eq!(
+            static_prefix("namespaces/vault/rbac_values.yaml"),
+            PathBuf::from("namespaces/vault")
+        );
+        assert_eq!(static_prefix("{{ project_name }}"), PathBuf::new());
+    }
+
+    #[test]
+    fn test_validate_against_checkout() {
+        let argo = tempfile::tempdir().unwrap();
+        let flux = tempfile::tempdir().unwrap();
+        let layout = Layout::default();
+
+        assert!(layout.validate_argo(argo.path()).is_err());
+        assert!(layout.validate_flux(flux.path()).is_err());
+
+        std::fs::create_dir_all(argo.path().join("manifests")).unwrap();
+        std::fs::create_dir_all(argo.path().join("applications")).unwrap();
+        std::fs::create_dir_all(flux.path().join("namespaces/vault/vault")).unwrap();
+        std::fs::create_dir_all(flux.path().join("namespaces/argocd/argocd-operator")).unwrap();
+        std::fs::write(
+            flux.path().join("namespaces/vault/vault/rbac_values.yaml"),
+            "",
+        )
+        .unwrap();
+        std::fs::write(
+            flux.path()
+                .join("namespaces/argocd/argocd-operator/rbac.yaml"),
+            "",
+        )
+        .unwrap();
+
+        layout.validate_argo(argo.path()).unwrap();
+        layout.validate_flux(flux.path()).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+use kube::api::{ObjectMeta, PostParams};
+use kube::{Api, Client};
+use std::time::Duration;
+
+use crate::controller::State;
+
+/// Lease based election of the replica running the controller
+#[derive(Clone)]
+pub struct LeaderElection {
+    client: Client,
+    /// Namespace of the Lease, the namespace of the operator
+    pub namespace: String,
+    /// Name of the Lease
+    pub name: String,
+    /// Identity of this replica, the pod name
+    pub identity: String,
+    /// Time a Lease that is not renewed stays held
+    pub lease_duration: Duration,
+    /// Interval between renewals, and between attempts of standby replicas
+    pub renew_interval: Duration,
+}
+
+impl LeaderElection {
+    //election configured with LEADER_ELECTION_LEASE, _LEASE_DURATION and _RENEW_INTERVAL, durations in seconds
+    pub fn from_env(client: Client) -> Self {
+        let seconds = |name: &str, default: u64| {
+            Duration::from_secs(
+                std::env::var(name)
+                    .ok()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+        let identity = std::env::var("POD_NAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_else(|_| format!("kyotu-project-operator-{}", std::process::id()));
+        Self {
+            client,
+            namespace: std::env::var("OPERATOR_NAMESPACE").expect("OPERATOR_NAMESPACE not set"),
+            name: std::env::var("LEADER_ELECTION_LEASE")
+                .unwrap_or("kyotu-project-operator".to_string()),
+            identity,
+            lease_duration: seconds("LEADER_ELECTION_LEASE_DURATION", 15),
+            renew_interval: seconds("LEADER_ELECTION_RENEW_INTERVAL", 5),
+        }
+    }
+
+    //take the lease when it is free or expired, or renew it, returning its holder
+    pub async fn try_acquire(&self) -> anyhow::Result<Option<String>> {
+        let lease_api: Api<Lease> = Api::namespaced(self.client.clone(), &self.namespace);
+        let now = Utc::now();
+        let Some(lease) = lease_api.get_opt(&self.name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.name.clone()),
+                    namespace: Some(self.namespace.clone()),
+                    ..Default::default()
+                },
+                spec: Some(self.held_spec(None, now)),
+            };
+            return match lease_api.create(&PostParams::default(), &lease).await {
+                Ok(_) => Ok(Some(self.identity.clone())),
+                //another replica created it first
+                Err(kube::Error::Api(e)) if e.code == 409 => Ok(None),
+                Err(e) => Err(e.into()),
+            };
+        };
+        let spec = lease.spec.clone().unwrap_or_default();
+        let holder = spec.holder_identity.clone();
+        if holder.as_ref() != Some(&self.identity) && !expired(&spec, now) {
+            return Ok(holder);
+        }
+        let lease = Lease {
+            spec: Some(self.held_spec(Some(&spec), now)),
+            ..lease
+        };
+        //the resource version makes a concurrent takeover fail with a conflict
+        match lease_api
+            .replace(&self.name, &PostParams::default(), &lease)
+            .await
+        {
+            Ok(_) => {
+                if holder.as_ref() != Some(&self.identity) {
+                    log::info!(
+                        "{} acquired lease {}, previously held by {}",
+                        self.identity,
+                        self.name,
+                        holder.as_deref().unwrap_or("nobody")
+                    );
+                }
+                Ok(Some(self.identity.clone()))
+            }
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(holder),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn held_spec(&self, previous: Option<&LeaseSpec>, now: DateTime<Utc>) -> LeaseSpec {
+        let renewing = previous
+            .is_some_and(|previous| previous.holder_identity.as_ref() == Some(&self.identity));
+        let transitions = previous
+            .and_then(|previous| previous.lease_transitions)
+            .unwrap_or(0);
+        LeaseSpec {
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(self.lease_duration.as_secs() as i32),
+            acquire_time: if renewing {
+                previous.and_then(|previous| previous.acquire_time.clone())
+            } else {
+                Some(MicroTime(now))
+            },
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: Some(if renewing || previous.is_none() {
+                transitions
+            } else {
+                transitions + 1
+            }),
+        }
+    }
+
+    //wait until this replica holds the lease, recording the current leader
+    pub async fn acquire(&self, state: &State) {
+        loop {
+            match self.try_acquire().await {
+                Ok(holder) => {
+                    let leading = holder.as_ref() == Some(&self.identity);
+                    state.set_leader(holder).await;
+                    if leading {
+                        log::info!("{} is the leader", self.identity);
+                        return;
+                    }
+                }
+                Err(e) => log::error!("Failed to acquire lease {}: {:?}", self.name, e),
+            }
+            tokio::time::sleep(self.renew_interval).await;
+        }
+    }
+
+    //renew the lease, returning once it is lost or could not be renewed with one renewal to spare before it expires,
+    //with the time left before another replica can take it
+    pub async fn hold(&self, state: &State) -> std::time::Duration {
+        let mut renewed = tokio::time::Instant::now();
+        //another replica takes the lease once it expires, stepping down earlier leaves time to stop reconciling
+        let step_down_after = self.lease_duration.saturating_sub(self.renew_interval);
+        loop {
+            tokio::time::sleep(self.renew_interval).await;
+            let renewal = tokio::time::timeout(self.renew_interval, self.try_acquire())
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("renewal timed out")));
+            match renewal {
+                Ok(holder) if holder.as_ref() == Some(&self.identity) => {
+                    renewed = tokio::time::Instant::now();
+                }
+                Ok(holder) => {
+                    log::error!(
+                        "Lease {} was taken over by {}",
+                        self.name,
+                        holder.as_deref().unwrap_or("nobody")
+                    );
+                    state.set_leader(holder).await;
+                    return std::time::Duration::ZERO;
+                }
+                Err(e) => {
+                    log::error!("Failed to renew lease {}: {:?}", self.name, e);
+                    if renewed.elapsed() >= step_down_after {
+                        state.set_leader(None).await;
+                        return self.lease_duration.saturating_sub(renewed.elapsed());
+                    }
+                }
+            }
+        }
+    }
+
+    //give the lease up so a standby replica takes over without waiting for it to expire
+    pub async fn release(&self) -> anyhow::Result<()> {
+        let lease_api: Api<Lease> = Api::namespaced(self.client.clone(), &self.namespace);
+        let Some(lease) = lease_api.get_opt(&self.name).await? else {
+            return Ok(());
+        };
+        let spec = lease.spec.clone().unwrap_or_default();
+        if spec.holder_identity.as_ref() != Some(&self.identity) {
+            return Ok(());
+        }
+        let lease = Lease {
+            spec: Some(LeaseSpec {
+                holder_identity: None,
+                acquire_time: None,
+                renew_time: None,
+                ..spec
+            }),
+            ..lease
+        };
+        lease_api
+            .replace(&self.name, &PostParams::default(), &lease)
+            .await?;
+        log::info!("{} released lease {}", self.identity, self.name);
+        Ok(())
+    }
+}
+
+//a lease without holder, or not renewed within its duration, can be taken
+fn expired(spec: &LeaseSpec, now: DateTime<Utc>) -> bool {
+    if spec.holder_identity.is_none() {
+        return true;
+    }
+    let Some(MicroTime(renewed)) = spec.renew_time else {
+        return true;
+    };
+    let duration = chrono::Duration::seconds(spec.lease_duration_seconds.unwrap_or(0).into());
+    renewed + duration < now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(holder: Option<&str>, renewed_ago: i64) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: holder.map(str::to_string),
+            lease_duration_seconds: Some(15),
+            renew_time: Some(MicroTime(
+                Utc::now() - chrono::Duration::seconds(renewed_ago),
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_expired() {
+        let now = Utc::now();
+        assert!(!expired(&spec(Some("operator-0"), 5), now));
+        assert!(expired(&spec(Some("operator-0"), 20), now));
+        assert!(expired(&spec(None, 5), now));
+        let unrenewed = LeaseSpec {
+            renew_time: None,
+            ..spec(Some("operator-0"), 0)
+        };
+        assert!(expired(&unrenewed, now));
+    }
+}
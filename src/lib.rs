@@ -9,25 +9,80 @@ mod gitlab;
 pub use gitlab::Gitlab;
 
 mod project_crd;
-pub use project_crd::Project;
+pub use project_crd::{
+    GitOpsCommit, GitOpsStatus, NamespaceSpec, NetworkProfile, NetworkSpec, Project, ProjectStatus,
+    QuotaProfile, QuotaSpec, VaultPath, VaultSpec,
+};
 
 mod namespace;
-pub use namespace::{create_namespace, delete_namespace};
+pub use namespace::{
+    apply_namespace_metadata, create_namespace, delete_namespace, namespace_metadata,
+    namespace_ownership, Ownership,
+};
+
+mod leader;
+pub use leader::LeaderElection;
+
+mod requeue;
+pub use requeue::Requeue;
+
+mod scope;
+pub use scope::Scope;
+
+mod shutdown;
+pub use shutdown::Shutdown;
+
+mod owner;
+pub use owner::{find_orphans, Orphan, ProjectOwner};
 
 mod finalizer;
 pub use finalizer::{add, delete};
 
+pub mod status;
+
 mod project;
 pub use project::{create_project, delete_project};
 
+mod network_policy;
+pub use network_policy::{apply_network_policies, delete_network_policies};
+
+mod quota;
+pub use quota::{apply_quota, delete_quota, Quota, QuotaError};
+
+mod rolebinding;
+pub use rolebinding::{create_rolebinding, delete_rolebinding};
+
 mod secret;
 pub use secret::{create_secret, delete_secret};
 
+mod argo_rbac;
+pub use argo_rbac::{ArgoRbacError, Policy, PolicyRule, RbacManifest};
+
+mod vault_policy;
+pub use vault_policy::VaultPolicyError;
+
+mod vault;
+pub use vault::{GitOpsValues, VaultAccess, VaultApi, VaultAuth, VaultBackend, VaultError};
+
+mod yaml_lines;
+
+mod vault_values;
+pub use vault_values::VaultValuesError;
+
 mod rbacs;
 pub use rbacs::{add_rbacs, remove_rbacs};
 
+mod credentials;
+pub use credentials::{CredentialsError, GitCredentials, GitHubApp};
+
+mod layout;
+pub use layout::{Layout, LayoutError, ProjectLayout, ProjectVars};
+
+mod templates;
+pub use templates::{TemplateError, Templates};
+
 mod repository;
-pub use repository::Repository;
+pub use repository::{Changes, KnownHosts, Outcome, Plan, Repository};
 
 use thiserror::Error;
 
@@ -49,6 +104,15 @@ pub enum Error {
 
     #[error("Invalid Project CRD: {0}")]
     UserInputError(String),
+
+    #[error("GitOps Error: {0}")]
+    GitOpsError(String),
+
+    #[error("Namespace Error: {0}")]
+    NamespaceError(String),
+
+    #[error("Layout Error: {0}")]
+    LayoutError(#[source] LayoutError),
 }
 
 impl Error {
@@ -1,4 +1,6 @@
-use actix_web::{get, web::Data, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, web::Data, web::Path, HttpRequest, HttpResponse, Responder};
+use clap::Parser;
+use controller::LeaderElection;
 pub use controller::State;
 use prometheus::{Encoder, TextEncoder};
 use serde::{Deserialize, Serialize};
@@ -6,6 +8,15 @@ use tokio::signal::unix::{signal, SignalKind};
 use tracing::info;
 use tracing_actix_web::TracingLogger;
 
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Only plan changes: edit the GitOps working trees and publish diffs, never commit,
+    /// push or call GitLab and Kubernetes
+    #[arg(long, env = "DRY_RUN")]
+    dry_run: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Health {
     status: String,
@@ -27,6 +38,28 @@ async fn metrics(c: Data<State>, _req: HttpRequest) -> impl Responder {
     HttpResponse::Ok().body(buffer)
 }
 
+#[get("/plans")]
+async fn plans(c: Data<State>, _req: HttpRequest) -> impl Responder {
+    HttpResponse::Ok().json(c.plans().await)
+}
+
+#[get("/plans/{namespace}/{name}")]
+async fn plan(c: Data<State>, path: Path<(String, String)>) -> impl Responder {
+    let (namespace, name) = path.into_inner();
+    match c.plans().await.get(&format!("{namespace}/{name}")) {
+        Some(plan) => {
+            let diff = plan
+                .argo
+                .iter()
+                .chain(plan.flux.iter())
+                .map(|p| format!("# {} ({})\n{}", p.repo, p.branch, p.diff))
+                .collect::<String>();
+            HttpResponse::Ok().content_type("text/x-diff").body(diff)
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
 #[get("/")]
 async fn index(c: Data<State>, _req: HttpRequest) -> impl Responder {
     let d = c.diagnostics().await;
@@ -37,6 +70,7 @@ async fn index(c: Data<State>, _req: HttpRequest) -> impl Responder {
 async fn main() -> anyhow::Result<()> {
     //init dotenv
     dotenv::dotenv().ok();
+    let args = Args::parse();
     //set tracing log level based on env var
     let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
     let log_level = match log_level.as_str() {
@@ -54,7 +88,53 @@ async fn main() -> anyhow::Result<()> {
         .json()
         .init();
     let state = State::default();
-    let contro = tokio::spawn(controller::run(state.clone()));
+    if args.dry_run {
+        info!("Running in dry-run mode, changes are planned but never applied");
+    }
+    let election = match std::env::var("LEADER_ELECTION").as_deref() {
+        Ok("true") => Some(LeaderElection::from_env(kube::Client::try_default().await?)),
+        _ => None,
+    };
+    let shutdown = state.shutdown();
+    let drain_timeout = std::time::Duration::from_secs(
+        std::env::var("SHUTDOWN_DRAIN_TIMEOUT")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(60),
+    );
+    //standby replicas only serve health checks and metrics until they hold the lease
+    let mut contro = {
+        let state = state.clone();
+        let election = election.clone();
+        tokio::spawn(async move {
+            match election {
+                Some(election) => {
+                    tokio::select! {
+                        _ = election.acquire(&state) => {},
+                        _ = state.shutdown().triggered() => return,
+                    }
+                    let run = controller::run(state.clone(), args.dry_run);
+                    tokio::pin!(run);
+                    tokio::select! {
+                        _ = &mut run => {},
+                        remaining = election.hold(&state) => {
+                            //running reconciles are drained as on shutdown, but only until the lease expires
+                            let drain_timeout = drain_timeout.min(remaining);
+                            info!("Leadership lost, draining the controller for {}s", drain_timeout.as_secs());
+                            state.shutdown().trigger();
+                            if tokio::time::timeout(drain_timeout, run).await.is_err() {
+                                log::warn!(
+                                    "Controller did not drain within {}s, stopping it",
+                                    drain_timeout.as_secs()
+                                );
+                            }
+                        },
+                    }
+                }
+                None => controller::run(state, args.dry_run).await,
+            }
+        })
+    };
 
     //start server for health check and metrics
     let srv = actix_web::HttpServer::new(move || {
@@ -64,12 +144,17 @@ async fn main() -> anyhow::Result<()> {
             .service(index)
             .service(health)
             .service(metrics)
+            .service(plans)
+            .service(plan)
     })
     .bind("0.0.0.0:8080")
     .expect("Failed to bind to port 8080")
-    .shutdown_timeout(5);
-
-    let server = tokio::spawn(srv.run());
+    //signals are handled below, so the server outlives the controller drain
+    .disable_signals()
+    .shutdown_timeout(5)
+    .run();
+    let server_handle = srv.handle();
+    let mut server = tokio::spawn(srv);
 
     let mut sigterm = signal(SignalKind::terminate()).unwrap();
     let mut sigint = signal(SignalKind::interrupt()).unwrap();
@@ -81,13 +166,35 @@ async fn main() -> anyhow::Result<()> {
         _ = sigint.recv() => {
             info!("SIGINT received");
         },
-        _ = server => {
+        _ = &mut server => {
             info!("Server stopped");
         },
-        _ = contro => {
+        _ = &mut contro => {
             info!("Controller stopped");
         }
     }
 
+    //stop scheduling reconciles and wait for the running ones to finish
+    shutdown.trigger();
+    //a handle already awaited above must not be polled again
+    if !contro.is_finished()
+        && tokio::time::timeout(drain_timeout, &mut contro)
+            .await
+            .is_err()
+    {
+        log::warn!(
+            "Controller did not drain within {}s, aborting it",
+            drain_timeout.as_secs()
+        );
+        contro.abort();
+    }
+
+    if let Some(election) = election {
+        if let Err(e) = election.release().await {
+            log::error!("Failed to release lease: {:?}", e);
+        }
+    }
+    server_handle.stop(true).await;
+
     Ok(())
 }
@@ -1,5 +1,7 @@
-use crate::{project_crd::Project, Error};
-use prometheus::{histogram_opts, opts, HistogramVec, IntCounter, IntCounterVec, Registry};
+use crate::{owner::Orphan, project_crd::Project, Error};
+use prometheus::{
+    histogram_opts, opts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Registry,
+};
 use tokio::time::Instant;
 
 #[derive(Clone)]
@@ -7,6 +9,9 @@ pub struct Metrics {
     pub reconciliations: IntCounter,
     pub failures: IntCounterVec,
     pub reconcile_duration: HistogramVec,
+    pub orphans: IntGaugeVec,
+    pub consecutive_errors: IntGaugeVec,
+    pub error_backoff: IntGaugeVec,
 }
 
 impl Default for Metrics {
@@ -30,10 +35,37 @@ impl Default for Metrics {
         .unwrap();
         let reconciliations =
             IntCounter::new("proj_controller_reconciliations_total", "reconciliations").unwrap();
+        let orphans = IntGaugeVec::new(
+            opts!(
+                "proj_controller_orphaned_objects",
+                "objects labelled for Projects that no longer exist",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let consecutive_errors = IntGaugeVec::new(
+            opts!(
+                "proj_controller_consecutive_errors",
+                "reconciliation errors in a row, reset on success",
+            ),
+            &["namespace", "instance"],
+        )
+        .unwrap();
+        let error_backoff = IntGaugeVec::new(
+            opts!(
+                "proj_controller_error_backoff_seconds",
+                "delay before the failed reconciliation is retried",
+            ),
+            &["namespace", "instance"],
+        )
+        .unwrap();
         Metrics {
             reconciliations,
             failures,
             reconcile_duration,
+            orphans,
+            consecutive_errors,
+            error_backoff,
         }
     }
 }
@@ -44,6 +76,9 @@ impl Metrics {
         registry.register(Box::new(self.reconcile_duration.clone()))?;
         registry.register(Box::new(self.failures.clone()))?;
         registry.register(Box::new(self.reconciliations.clone()))?;
+        registry.register(Box::new(self.orphans.clone()))?;
+        registry.register(Box::new(self.consecutive_errors.clone()))?;
+        registry.register(Box::new(self.error_backoff.clone()))?;
         Ok(self)
     }
 
@@ -56,6 +91,31 @@ impl Metrics {
             .inc()
     }
 
+    pub fn backoff(&self, proj: &Project, errors: u32, delay: std::time::Duration) {
+        let labels = backoff_labels(proj);
+        self.consecutive_errors
+            .with_label_values(&labels)
+            .set(errors.into());
+        self.error_backoff
+            .with_label_values(&labels)
+            .set(delay.as_secs() as i64);
+    }
+
+    pub fn reset_backoff(&self, proj: &Project) {
+        let labels = backoff_labels(proj);
+        let _ = self.consecutive_errors.remove_label_values(&labels);
+        let _ = self.error_backoff.remove_label_values(&labels);
+    }
+
+    pub fn set_orphans(&self, orphans: &[Orphan]) {
+        self.orphans.reset();
+        for orphan in orphans {
+            self.orphans
+                .with_label_values(&[orphan.kind.as_str()])
+                .inc();
+        }
+    }
+
     pub fn count_and_measure(&self) -> ReconcileMeasurer {
         self.reconciliations.inc();
         ReconcileMeasurer {
@@ -65,6 +125,14 @@ impl Metrics {
     }
 }
 
+//namespace and name of a Project, names are only unique within a namespace
+fn backoff_labels(proj: &Project) -> [&str; 2] {
+    [
+        proj.metadata.namespace.as_deref().unwrap_or_default(),
+        proj.metadata.name.as_deref().unwrap_or_default(),
+    ]
+}
+
 /// Smart function duration measurer
 ///
 /// Relies on Drop to calculate duration and register the observation in the histogram
@@ -1,36 +1,139 @@
-use k8s_openapi::api::core::v1::Namespace;
-use kube::api::{DeleteParams, ObjectMeta, PostParams};
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::{Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use kube::api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PostParams};
 use kube::{Api, Client};
+use serde_json::json;
 use std::collections::BTreeMap;
 
-//create namespace
-pub async fn create_namespace(client: Client, name: &str) -> anyhow::Result<String> {
+use crate::layout::ProjectVars;
+use crate::owner::{ProjectOwner, PROJECT_NAMESPACE_LABEL, PROJECT_NAME_LABEL, PROJECT_UID_LABEL};
+use crate::project_crd::NamespaceSpec;
+use crate::secret::PULL_SECRET_NAME;
+
+/// Label marking namespaces created, and deleted, by the operator
+pub const OWNERSHIP_LABEL: &str = "app";
+
+/// Pod Security Admission label enforced on project namespaces
+pub const PSA_ENFORCE_LABEL: &str = "pod-security.kubernetes.io/enforce";
+
+/// Annotation naming the Project owning a namespace, as `<namespace>/<name>`
+pub const OWNED_BY_ANNOTATION: &str = "kyotu.tech/owned-by";
+
+/// Annotation holding the UID of the Project owning a namespace
+pub const OWNER_UID_ANNOTATION: &str = "kyotu.tech/owner-uid";
+
+/// Label set on namespaces scaled down and waiting for deletion
+pub const PENDING_DELETION_LABEL: &str = "kyotu.tech/pending-deletion";
+
+/// Annotation holding the time after which a pending namespace is deleted
+pub const DELETE_AFTER_ANNOTATION: &str = "kyotu.tech/delete-after";
+
+/// Outcome of claiming a namespace for a Project
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ownership {
+    Created,
+    Owned,
+    /// Existing namespace annotated for the Project
+    Adopted,
+    /// Namespace the Project must not manage, with the reason
+    Conflict(String),
+}
+
+impl ProjectOwner {
+    fn annotations(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (OWNED_BY_ANNOTATION.to_string(), self.project()),
+            (OWNER_UID_ANNOTATION.to_string(), self.uid.clone()),
+        ])
+    }
+
+    //whether the namespace is annotated for this exact Project
+    pub fn owns(&self, namespace: &Namespace) -> bool {
+        let annotations = namespace.metadata.annotations.clone().unwrap_or_default();
+        annotations.get(OWNED_BY_ANNOTATION) == Some(&self.project())
+            && annotations.get(OWNER_UID_ANNOTATION) == Some(&self.uid)
+    }
+
+    //decide how an existing namespace can be claimed
+    pub fn claim(&self, namespace: &Namespace, adopt: bool) -> Ownership {
+        if self.owns(namespace) {
+            return Ownership::Owned;
+        }
+        let name = namespace.metadata.name.clone().unwrap_or_default();
+        let annotations = namespace.metadata.annotations.clone().unwrap_or_default();
+        if let Some(owner) = annotations.get(OWNED_BY_ANNOTATION) {
+            let uid = annotations
+                .get(OWNER_UID_ANNOTATION)
+                .map(String::as_str)
+                .unwrap_or("unknown");
+            return Ownership::Conflict(format!(
+                "Namespace {name} is owned by Project {owner} with uid {uid}"
+            ));
+        }
+        //the operator label alone does not tell which Project a namespace was created for
+        let labelled = namespace
+            .metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(PROJECT_UID_LABEL))
+            .is_some_and(|uid| !self.uid.is_empty() && uid == &self.uid);
+        if adopt || labelled {
+            Ownership::Adopted
+        } else {
+            Ownership::Conflict(format!(
+                "Namespace {name} already exists, set spec.adoptExistingNamespace to adopt it"
+            ))
+        }
+    }
+}
+
+//create namespace, or claim an existing one for the project
+pub async fn create_namespace(
+    client: Client,
+    name: &str,
+    owner: &ProjectOwner,
+    adopt: bool,
+) -> anyhow::Result<Ownership> {
     let mut labels: BTreeMap<String, String> = BTreeMap::new();
-    labels.insert("app".to_string(), "kyotu-project-operator".to_string());
+    labels.insert(
+        OWNERSHIP_LABEL.to_string(),
+        "kyotu-project-operator".to_string(),
+    );
 
-    let namespace = Namespace {
+    let mut namespace = Namespace {
         metadata: ObjectMeta {
             name: Some(name.to_string()),
             labels: Some(labels),
+            annotations: Some(owner.annotations()),
             ..Default::default()
         },
         ..Default::default()
     };
+    owner.own(&mut namespace.metadata);
     let ns_api: Api<Namespace> = Api::all(client);
 
     //check if namespace exists
-    let res = ns_api.get(name).await;
-    match res {
-        Ok(_) => {
-            log::warn!("Namespace {} already exists", name);
-            Ok(name.to_string())
+    match ns_api.get_opt(name).await? {
+        Some(existing) => {
+            let ownership = owner.claim(&existing, adopt);
+            if ownership == Ownership::Adopted {
+                let patch = json!({
+                    "metadata": { "labels": owner.labels(), "annotations": owner.annotations() }
+                });
+                ns_api
+                    .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+                    .await?;
+                log::info!("Adopted namespace {} for {}", name, owner.project());
+            }
+            Ok(ownership)
         }
-        Err(_) => {
+        None => {
             let res = ns_api.create(&PostParams::default(), &namespace).await;
             match res {
                 Ok(r) => {
                     log::info!("Created namespace {}", r.metadata.name.unwrap());
-                    Ok(name.to_string())
+                    Ok(Ownership::Created)
                 }
                 Err(e) => {
                     log::error!("Error creating namespace: {}", e);
@@ -41,31 +144,398 @@ pub async fn create_namespace(client: Client, name: &str) -> anyhow::Result<Stri
     }
 }
 
-//delete namespace
-pub async fn delete_namespace(client: Client, name: &str) -> anyhow::Result<String> {
+//how the Project holds its existing namespace, none when the namespace does not exist
+pub async fn namespace_ownership(
+    client: Client,
+    name: &str,
+    owner: &ProjectOwner,
+) -> anyhow::Result<Option<Ownership>> {
     let ns_api: Api<Namespace> = Api::all(client);
-    //delete only if label app=kyotu-project-operator is present
-    let res = ns_api.get(name).await;
-
-    match res {
-        Ok(_) => {
-            let labels = res.unwrap().metadata.labels.unwrap();
-            if labels.get("app").unwrap_or(&"none".to_string()) != "kyotu-project-operator" {
-                log::warn!(
-                    "Namespace {} does not have label app=kyotu-project-operator",
-                    name
-                );
-                Ok(name.to_string())
-            } else {
-                let dp = DeleteParams::default();
-                let _res = ns_api.delete(name, &dp).await?;
-                log::info!("Deleted namespace {}", name);
-                Ok(name.to_string())
-            }
+    Ok(ns_api
+        .get_opt(name)
+        .await?
+        .map(|existing| owner.claim(&existing, false)))
+}
+
+//what was removed from a provisioned project namespace, none while it is terminating
+pub async fn namespace_drift(client: Client, name: &str) -> anyhow::Result<Option<String>> {
+    let ns_api: Api<Namespace> = Api::all(client.clone());
+    let Some(existing) = ns_api.get_opt(name).await? else {
+        return Ok(Some(format!("Namespace {name} was deleted")));
+    };
+    if existing.metadata.deletion_timestamp.is_some() {
+        return Ok(None);
+    }
+    let secret_api: Api<Secret> = Api::namespaced(client, name);
+    Ok(match secret_api.get_opt(PULL_SECRET_NAME).await? {
+        Some(_) => None,
+        None => Some(format!(
+            "Secret {PULL_SECRET_NAME} was deleted from namespace {name}"
+        )),
+    })
+}
+
+//scale an owned namespace down and mark it pending deletion, returning the time left before it can be deleted
+pub async fn schedule_deletion(
+    client: Client,
+    name: &str,
+    owner: &ProjectOwner,
+    grace_period: std::time::Duration,
+) -> anyhow::Result<Option<std::time::Duration>> {
+    let ns_api: Api<Namespace> = Api::all(client.clone());
+    let Some(existing) = ns_api.get_opt(name).await? else {
+        return Ok(None);
+    };
+    if !owner.owns(&existing) {
+        return Ok(None);
+    }
+    let now = Utc::now();
+    let delete_after = existing
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(DELETE_AFTER_ANNOTATION))
+        .and_then(|after| DateTime::parse_from_rfc3339(after).ok())
+        .map(|after| after.with_timezone(&Utc));
+    let delete_after = match delete_after {
+        Some(after) => after,
+        None => {
+            let after = now + chrono::Duration::from_std(grace_period)?;
+            scale_down(client, name).await?;
+            let patch = json!({
+                "metadata": {
+                    "labels": { PENDING_DELETION_LABEL: "true" },
+                    "annotations": { DELETE_AFTER_ANNOTATION: after.to_rfc3339() },
+                }
+            });
+            ns_api
+                .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+                .await?;
+            log::info!("Namespace {} scaled down, deleting after {}", name, after);
+            after
+        }
+    };
+    Ok((delete_after - now).to_std().ok())
+}
+
+//scale deployments and statefulsets of a namespace to zero
+async fn scale_down(client: Client, namespace: &str) -> anyhow::Result<()> {
+    let scale = json!({ "spec": { "replicas": 0 } });
+    let params = PatchParams::default();
+    let deployments: Api<Deployment> = Api::namespaced(client.clone(), namespace);
+    for deployment in deployments.list(&ListParams::default()).await? {
+        let name = deployment.metadata.name.unwrap_or_default();
+        deployments
+            .patch_scale(&name, &params, &Patch::Merge(&scale))
+            .await?;
+    }
+    let statefulsets: Api<StatefulSet> = Api::namespaced(client, namespace);
+    for statefulset in statefulsets.list(&ListParams::default()).await? {
+        let name = statefulset.metadata.name.unwrap_or_default();
+        statefulsets
+            .patch_scale(&name, &params, &Patch::Merge(&scale))
+            .await?;
+    }
+    Ok(())
+}
+
+//delete namespace, only when it is annotated for this exact project
+pub async fn delete_namespace(
+    client: Client,
+    name: &str,
+    owner: &ProjectOwner,
+) -> anyhow::Result<String> {
+    let ns_api: Api<Namespace> = Api::all(client);
+
+    match ns_api.get_opt(name).await? {
+        Some(existing) if owner.owns(&existing) => {
+            let dp = DeleteParams::default();
+            let _res = ns_api.delete(name, &dp).await?;
+            log::info!("Deleted namespace {}", name);
+            Ok(name.to_string())
+        }
+        Some(_) => {
+            log::warn!(
+                "Namespace {} is not owned by Project {}, not deleting it",
+                name,
+                owner.project()
+            );
+            Ok(name.to_string())
         }
-        Err(_) => {
+        None => {
             log::warn!("Namespace {} does not exist", name);
             Ok(name.to_string())
         }
     }
 }
+
+//pod security level for an environment, PSA_ENFORCE_<ENVIRONMENT> overrides restricted for prod and baseline otherwise
+pub fn pod_security_level(environment_type: &str) -> String {
+    std::env::var(format!("PSA_ENFORCE_{}", environment_type.to_uppercase())).unwrap_or_else(|_| {
+        match environment_type {
+            "prod" => "restricted".to_string(),
+            _ => std::env::var("PSA_ENFORCE").unwrap_or("baseline".to_string()),
+        }
+    })
+}
+
+//labels and annotations of a project namespace, the spec cannot override the ones set by the operator
+pub fn namespace_metadata(
+    project: &ProjectVars,
+    owner: &ProjectOwner,
+    spec: Option<&NamespaceSpec>,
+) -> (BTreeMap<String, String>, BTreeMap<String, String>) {
+    let mut labels = BTreeMap::new();
+    let mut annotations = BTreeMap::new();
+    if let Some(spec) = spec {
+        let reserved_labels = [
+            OWNERSHIP_LABEL,
+            PENDING_DELETION_LABEL,
+            PROJECT_UID_LABEL,
+            PROJECT_NAME_LABEL,
+            PROJECT_NAMESPACE_LABEL,
+        ];
+        let reserved_annotations = [
+            OWNED_BY_ANNOTATION,
+            OWNER_UID_ANNOTATION,
+            DELETE_AFTER_ANNOTATION,
+        ];
+        for (kind, values, reserved, metadata) in [
+            ("label", &spec.labels, &reserved_labels[..], &mut labels),
+            (
+                "annotation",
+                &spec.annotations,
+                &reserved_annotations[..],
+                &mut annotations,
+            ),
+        ] {
+            for (key, value) in values {
+                if reserved.contains(&key.as_str()) {
+                    log::warn!(
+                        "Ignoring {} {} of namespace {}, it is set by the operator",
+                        kind,
+                        key,
+                        project.project_name
+                    );
+                    continue;
+                }
+                metadata.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    let team = project.google_group.split('@').next().unwrap_or_default();
+    labels.insert("team".to_string(), label_value(team));
+    labels.insert("project-id".to_string(), label_value(&project.project_id));
+    labels.insert(
+        "environment".to_string(),
+        label_value(&project.environment_type),
+    );
+    labels.insert(
+        PSA_ENFORCE_LABEL.to_string(),
+        pod_security_level(&project.environment_type),
+    );
+    labels.extend(owner.labels());
+    (labels, annotations)
+}
+
+//label values are up to 63 alphanumerics, '-', '_' or '.', starting and ending with an alphanumeric
+fn label_value(value: &str) -> String {
+    let value = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .take(63)
+        .collect::<String>();
+    value
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+//apply labels and annotations, also on namespaces the operator did not create
+pub async fn apply_namespace_metadata(
+    client: Client,
+    name: &str,
+    labels: &BTreeMap<String, String>,
+    annotations: &BTreeMap<String, String>,
+) -> anyhow::Result<String> {
+    let ns_api: Api<Namespace> = Api::all(client);
+    //an apply patch would create a namespace removed since
+    if ns_api.get_opt(name).await?.is_none() {
+        anyhow::bail!("Namespace {name} does not exist");
+    }
+    let patch = json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {
+            "name": name,
+            "labels": labels,
+            "annotations": annotations,
+        }
+    });
+    ns_api
+        .patch(
+            name,
+            &PatchParams::apply("kyotu-project-operator").force(),
+            &Patch::Apply(&patch),
+        )
+        .await?;
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(environment_type: &str) -> ProjectVars {
+        ProjectVars {
+            project_name: format!("demo-{environment_type}"),
+            project_id: "demo".to_string(),
+            environment_type: environment_type.to_string(),
+            namespace: "default".to_string(),
+            google_group: "demo.crew@kyotu.tech".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_namespace_metadata() {
+        let (labels, annotations) = namespace_metadata(&project("prod"), &owner(), None);
+        assert_eq!(labels["team"], "demo.crew");
+        assert_eq!(labels["project-id"], "demo");
+        assert_eq!(labels["environment"], "prod");
+        assert_eq!(labels[PSA_ENFORCE_LABEL], "restricted");
+        assert!(!labels.contains_key(OWNERSHIP_LABEL));
+        assert!(annotations.is_empty());
+
+        let (labels, _) = namespace_metadata(&project("dev"), &owner(), None);
+        assert_eq!(labels[PSA_ENFORCE_LABEL], "baseline");
+    }
+
+    #[test]
+    fn test_spec_cannot_override_operator_labels() {
+        let spec = NamespaceSpec {
+            labels: BTreeMap::from([
+                ("app".to_string(), "mine".to_string()),
+                ("cost-center".to_string(), "r-and-d".to_string()),
+                (PSA_ENFORCE_LABEL.to_string(), "privileged".to_string()),
+                (PROJECT_UID_LABEL.to_string(), "0000".to_string()),
+            ]),
+            annotations: BTreeMap::from([("owner".to_string(), "demo@kyotu.tech".to_string())]),
+        };
+        let (labels, annotations) = namespace_metadata(&project("dev"), &owner(), Some(&spec));
+        assert!(!labels.contains_key(OWNERSHIP_LABEL));
+        assert_eq!(labels["cost-center"], "r-and-d");
+        assert_eq!(labels[PSA_ENFORCE_LABEL], "baseline");
+        assert_eq!(labels[PROJECT_UID_LABEL], "4f1c");
+        assert_eq!(annotations["owner"], "demo@kyotu.tech");
+    }
+
+    #[test]
+    fn test_spec_cannot_override_operator_annotations() {
+        let spec = NamespaceSpec {
+            labels: BTreeMap::from([(PENDING_DELETION_LABEL.to_string(), "true".to_string())]),
+            annotations: BTreeMap::from([
+                (OWNED_BY_ANNOTATION.to_string(), "other/demo".to_string()),
+                (OWNER_UID_ANNOTATION.to_string(), "0000".to_string()),
+                (
+                    DELETE_AFTER_ANNOTATION.to_string(),
+                    "2020-01-01T00:00:00Z".to_string(),
+                ),
+                ("owner".to_string(), "demo@kyotu.tech".to_string()),
+            ]),
+        };
+        let (labels, annotations) = namespace_metadata(&project("dev"), &owner(), Some(&spec));
+        assert!(!labels.contains_key(PENDING_DELETION_LABEL));
+        assert_eq!(
+            annotations,
+            BTreeMap::from([("owner".to_string(), "demo@kyotu.tech".to_string())])
+        );
+    }
+
+    fn owner() -> ProjectOwner {
+        ProjectOwner {
+            name: "demo".to_string(),
+            namespace: "default".to_string(),
+            uid: "4f1c".to_string(),
+        }
+    }
+
+    fn namespace(labels: &[(&str, &str)], annotations: &[(&str, &str)]) -> Namespace {
+        let to_map = |values: &[(&str, &str)]| {
+            values
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some("demo-dev".to_string()),
+                labels: Some(to_map(labels)),
+                annotations: Some(to_map(annotations)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_claim_existing_namespace() {
+        let owned = namespace(
+            &[],
+            &[
+                (OWNED_BY_ANNOTATION, "default/demo"),
+                (OWNER_UID_ANNOTATION, "4f1c"),
+            ],
+        );
+        assert_eq!(owner().claim(&owned, false), Ownership::Owned);
+        assert!(owner().owns(&owned));
+
+        //a recreated project with the same name is a different owner
+        let recreated = ProjectOwner {
+            uid: "9a2b".to_string(),
+            ..owner()
+        };
+        assert!(matches!(
+            recreated.claim(&owned, true),
+            Ownership::Conflict(_)
+        ));
+        assert!(!recreated.owns(&owned));
+
+        let foreign = namespace(&[], &[]);
+        assert!(matches!(
+            owner().claim(&foreign, false),
+            Ownership::Conflict(_)
+        ));
+        assert_eq!(owner().claim(&foreign, true), Ownership::Adopted);
+        assert!(!owner().owns(&foreign));
+
+        //namespaces created before ownership annotations are only adopted when asked to
+        let legacy = namespace(&[(OWNERSHIP_LABEL, "kyotu-project-operator")], &[]);
+        assert!(matches!(
+            owner().claim(&legacy, false),
+            Ownership::Conflict(_)
+        ));
+        assert_eq!(owner().claim(&legacy, true), Ownership::Adopted);
+
+        //unless they carry the uid of the Project
+        let labelled = namespace(
+            &[
+                (OWNERSHIP_LABEL, "kyotu-project-operator"),
+                (PROJECT_UID_LABEL, "4f1c"),
+            ],
+            &[],
+        );
+        assert_eq!(owner().claim(&labelled, false), Ownership::Adopted);
+        assert!(matches!(
+            recreated.claim(&labelled, false),
+            Ownership::Conflict(_)
+        ));
+    }
+
+    #[test]
+    fn test_label_value() {
+        assert_eq!(label_value("demo crew@"), "demo-crew");
+        assert_eq!(label_value(&"a".repeat(80)).len(), 63);
+    }
+}
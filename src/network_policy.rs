@@ -0,0 +1,199 @@
+use k8s_openapi::api::networking::v1::{
+    NetworkPolicy, NetworkPolicyIngressRule, NetworkPolicyPeer, NetworkPolicySpec,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::{Api, Client};
+use std::collections::BTreeMap;
+
+use crate::owner::ProjectOwner;
+use crate::project_crd::{NetworkProfile, NetworkSpec};
+
+/// Names of the NetworkPolicies managed in a project namespace
+pub const NETWORK_POLICY_NAMES: [&str; 5] = [
+    "kyotu-default-deny-ingress",
+    "kyotu-allow-same-namespace",
+    "kyotu-allow-ingress-controller",
+    "kyotu-allow-monitoring",
+    "kyotu-allow-peer-namespaces",
+];
+
+/// Label set by Kubernetes on every namespace
+const NAMESPACE_NAME_LABEL: &str = "kubernetes.io/metadata.name";
+
+//network policies of a namespace, none unless isolated
+pub fn network_policies(namespace: &str, spec: Option<&NetworkSpec>) -> Vec<NetworkPolicy> {
+    let Some(spec) = spec.filter(|spec| spec.profile == NetworkProfile::Isolated) else {
+        return vec![];
+    };
+    let ingress_namespace =
+        std::env::var("INGRESS_NAMESPACE").unwrap_or("ingress-nginx".to_string());
+    let monitoring_namespace =
+        std::env::var("MONITORING_NAMESPACE").unwrap_or("monitoring".to_string());
+
+    let mut policies = vec![
+        policy(namespace, NETWORK_POLICY_NAMES[0], None),
+        policy(
+            namespace,
+            NETWORK_POLICY_NAMES[1],
+            Some(NetworkPolicyPeer {
+                pod_selector: Some(LabelSelector::default()),
+                ..Default::default()
+            }),
+        ),
+        policy(
+            namespace,
+            NETWORK_POLICY_NAMES[2],
+            Some(from_namespaces(vec![ingress_namespace])),
+        ),
+        policy(
+            namespace,
+            NETWORK_POLICY_NAMES[3],
+            Some(from_namespaces(vec![monitoring_namespace])),
+        ),
+    ];
+    if !spec.allowed_namespaces.is_empty() {
+        policies.push(policy(
+            namespace,
+            NETWORK_POLICY_NAMES[4],
+            Some(from_namespaces(spec.allowed_namespaces.clone())),
+        ));
+    }
+    policies
+}
+
+//ingress policy selecting every pod, denying all ingress without a peer
+fn policy(namespace: &str, name: &str, peer: Option<NetworkPolicyPeer>) -> NetworkPolicy {
+    let mut labels: BTreeMap<String, String> = BTreeMap::new();
+    labels.insert("app".to_string(), "kyotu-project-operator".to_string());
+
+    NetworkPolicy {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(namespace.to_string()),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        spec: Some(NetworkPolicySpec {
+            pod_selector: LabelSelector::default(),
+            policy_types: Some(vec!["Ingress".to_string()]),
+            ingress: peer.map(|peer| {
+                vec![NetworkPolicyIngressRule {
+                    from: Some(vec![peer]),
+                    ports: None,
+                }]
+            }),
+            egress: None,
+        }),
+        ..Default::default()
+    }
+}
+
+fn from_namespaces(names: Vec<String>) -> NetworkPolicyPeer {
+    NetworkPolicyPeer {
+        namespace_selector: Some(LabelSelector {
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: NAMESPACE_NAME_LABEL.to_string(),
+                operator: "In".to_string(),
+                values: Some(names),
+            }]),
+            match_labels: None,
+        }),
+        ..Default::default()
+    }
+}
+
+//apply network policies of the spec, deleting managed ones no longer wanted
+pub async fn apply_network_policies(
+    client: Client,
+    namespace: &str,
+    spec: Option<&NetworkSpec>,
+    owner: &ProjectOwner,
+) -> anyhow::Result<String> {
+    let policy_api: Api<NetworkPolicy> = Api::namespaced(client, namespace);
+    let params = PatchParams::apply("kyotu-project-operator").force();
+    let mut policies = network_policies(namespace, spec);
+
+    for policy in &mut policies {
+        owner.own(&mut policy.metadata);
+        let name = policy.metadata.name.clone().unwrap();
+        policy_api
+            .patch(&name, &params, &Patch::Apply(&*policy))
+            .await?;
+    }
+    let wanted = policies
+        .iter()
+        .filter_map(|policy| policy.metadata.name.as_deref())
+        .collect::<Vec<_>>();
+    prune(&policy_api, namespace, &wanted).await?;
+    Ok(namespace.to_string())
+}
+
+//delete network policies
+pub async fn delete_network_policies(client: Client, namespace: &str) -> anyhow::Result<String> {
+    let policy_api: Api<NetworkPolicy> = Api::namespaced(client, namespace);
+    prune(&policy_api, namespace, &[]).await?;
+    Ok(namespace.to_string())
+}
+
+//delete managed policies that are not wanted
+async fn prune(
+    policy_api: &Api<NetworkPolicy>,
+    namespace: &str,
+    wanted: &[&str],
+) -> anyhow::Result<()> {
+    for name in NETWORK_POLICY_NAMES {
+        if !wanted.contains(&name) && policy_api.get_opt(name).await?.is_some() {
+            policy_api.delete(name, &DeleteParams::default()).await?;
+            log::info!("Deleted network policy {} in namespace {}", name, namespace);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(policies: &[NetworkPolicy]) -> Vec<&str> {
+        policies
+            .iter()
+            .map(|policy| policy.metadata.name.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_no_policies_unless_isolated() {
+        assert!(network_policies("demo-dev", None).is_empty());
+        let open = NetworkSpec {
+            profile: NetworkProfile::Open,
+            allowed_namespaces: vec!["shop-dev".to_string()],
+        };
+        assert!(network_policies("demo-dev", Some(&open)).is_empty());
+    }
+
+    #[test]
+    fn test_isolated_policies() {
+        let policies = network_policies("demo-dev", Some(&NetworkSpec::default()));
+        assert_eq!(names(&policies), NETWORK_POLICY_NAMES[..4].to_vec());
+        let deny = policies[0].spec.as_ref().unwrap();
+        assert_eq!(deny.policy_types, Some(vec!["Ingress".to_string()]));
+        assert!(deny.ingress.is_none());
+
+        let spec = NetworkSpec {
+            allowed_namespaces: vec!["shop-dev".to_string(), "gateway".to_string()],
+            ..Default::default()
+        };
+        let policies = network_policies("demo-dev", Some(&spec));
+        assert_eq!(names(&policies), NETWORK_POLICY_NAMES.to_vec());
+        let peers = policies[4].spec.as_ref().unwrap().ingress.as_ref().unwrap()[0]
+            .from
+            .as_ref()
+            .unwrap();
+        let selector = peers[0].namespace_selector.as_ref().unwrap();
+        assert_eq!(
+            selector.match_expressions.as_ref().unwrap()[0].values,
+            Some(vec!["shop-dev".to_string(), "gateway".to_string()])
+        );
+    }
+}
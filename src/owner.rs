@@ -0,0 +1,290 @@
+use k8s_openapi::api::core::v1::{LimitRange, Namespace, ResourceQuota, Secret};
+use k8s_openapi::api::networking::v1::NetworkPolicy;
+use k8s_openapi::api::rbac::v1::RoleBinding;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{ListParams, ObjectMeta};
+use kube::runtime::reflector::ObjectRef;
+use kube::{Api, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+
+use crate::project_crd::Project;
+use crate::secret::PULL_SECRET_NAME;
+
+/// Label holding the UID of the Project an object was created for
+pub const PROJECT_UID_LABEL: &str = "kyotu.tech/project-uid";
+
+/// Label holding the name of the Project an object was created for
+pub const PROJECT_NAME_LABEL: &str = "kyotu.tech/project-name";
+
+/// Label holding the namespace of the Project an object was created for
+pub const PROJECT_NAMESPACE_LABEL: &str = "kyotu.tech/project-namespace";
+
+/// Project owning the objects created for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectOwner {
+    pub name: String,
+    /// Namespace of the Project resource, not of the project namespace
+    pub namespace: String,
+    pub uid: String,
+}
+
+/// Labelled object whose Project no longer exists
+#[derive(Debug, Clone, PartialEq)]
+pub struct Orphan {
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+    /// `<namespace>/<name>` of the missing Project
+    pub project: String,
+}
+
+impl ProjectOwner {
+    pub fn from_project(project: &Project) -> Self {
+        Self {
+            name: project.name_any(),
+            namespace: project.namespace().unwrap_or_default(),
+            uid: project.metadata.uid.clone().unwrap_or_default(),
+        }
+    }
+
+    //`<namespace>/<name>` of the Project
+    pub fn project(&self) -> String {
+        format!("{}/{}", self.namespace, self.name)
+    }
+
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (PROJECT_UID_LABEL.to_string(), self.uid.clone()),
+            (PROJECT_NAME_LABEL.to_string(), self.name.clone()),
+            (PROJECT_NAMESPACE_LABEL.to_string(), self.namespace.clone()),
+        ])
+    }
+
+    //owner references are only legal on objects in the namespace of the Project
+    pub fn owner_references(&self, namespace: Option<&str>) -> Option<Vec<OwnerReference>> {
+        if namespace != Some(self.namespace.as_str()) || self.uid.is_empty() {
+            return None;
+        }
+        Some(vec![OwnerReference {
+            api_version: Project::api_version(&()).to_string(),
+            kind: Project::kind(&()).to_string(),
+            name: self.name.clone(),
+            uid: self.uid.clone(),
+            controller: Some(true),
+            block_owner_deletion: None,
+        }])
+    }
+
+    //add project labels, and an owner reference where legal, to the metadata of a new object
+    pub fn own(&self, metadata: &mut ObjectMeta) {
+        metadata
+            .labels
+            .get_or_insert_with(BTreeMap::new)
+            .extend(self.labels());
+        if let Some(references) = self.owner_references(metadata.namespace.as_deref()) {
+            metadata.owner_references = Some(references);
+        }
+    }
+
+    //whether an existing object carries the labels of this Project
+    pub fn labelled(&self, metadata: &ObjectMeta) -> bool {
+        metadata
+            .labels
+            .as_ref()
+            .is_some_and(|labels| self.labels().iter().all(|(k, v)| labels.get(k) == Some(v)))
+    }
+
+    //merge patch adding project labels, and an owner reference where legal, to an existing object
+    pub fn metadata_patch(&self, namespace: Option<&str>) -> serde_json::Value {
+        match self.owner_references(namespace) {
+            Some(references) => json!({
+                "metadata": { "labels": self.labels(), "ownerReferences": references }
+            }),
+            None => json!({ "metadata": { "labels": self.labels() } }),
+        }
+    }
+}
+
+//Project an object was created for, read from its labels
+pub fn project_ref(metadata: &ObjectMeta) -> Option<ObjectRef<Project>> {
+    let labels = metadata.labels.as_ref()?;
+    let name = labels.get(PROJECT_NAME_LABEL)?;
+    let namespace = labels.get(PROJECT_NAMESPACE_LABEL)?;
+    Some(ObjectRef::new(name).within(namespace))
+}
+
+//labelled objects whose project uid is not one of the existing Projects, for Projects in the namespaces, any when empty
+pub fn orphans(
+    objects: &[(String, ObjectMeta)],
+    uids: &BTreeSet<String>,
+    namespaces: &[String],
+) -> Vec<Orphan> {
+    objects
+        .iter()
+        .filter_map(|(kind, metadata)| {
+            let labels = metadata.labels.as_ref()?;
+            let uid = labels.get(PROJECT_UID_LABEL)?;
+            if uids.contains(uid) {
+                return None;
+            }
+            //objects of Projects in namespaces watched by another operator instance
+            if !namespaces.is_empty()
+                && !labels
+                    .get(PROJECT_NAMESPACE_LABEL)
+                    .is_some_and(|namespace| namespaces.contains(namespace))
+            {
+                return None;
+            }
+            let project = format!(
+                "{}/{}",
+                labels
+                    .get(PROJECT_NAMESPACE_LABEL)
+                    .map(String::as_str)
+                    .unwrap_or("unknown"),
+                labels
+                    .get(PROJECT_NAME_LABEL)
+                    .map(String::as_str)
+                    .unwrap_or("unknown")
+            );
+            Some(Orphan {
+                kind: kind.clone(),
+                namespace: metadata.namespace.clone(),
+                name: metadata.name.clone().unwrap_or_default(),
+                project,
+            })
+        })
+        .collect()
+}
+
+//metadata of every object of a kind carrying a project uid label
+async fn labelled<K>(
+    client: Client,
+    params: ListParams,
+) -> anyhow::Result<Vec<(String, ObjectMeta)>>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let api: Api<K> = Api::all(client);
+    let list = api.list_metadata(&params.labels(PROJECT_UID_LABEL)).await?;
+    Ok(list
+        .items
+        .into_iter()
+        .map(|object| (K::kind(&()).to_string(), object.metadata))
+        .collect())
+}
+
+//find objects created by the operator for Projects in the namespaces, any when empty, that no longer exist
+pub async fn find_orphans(client: Client, namespaces: &[String]) -> anyhow::Result<Vec<Orphan>> {
+    //every Project of the namespaces counts, also those of instances with another selector
+    let project_apis: Vec<Api<Project>> = if namespaces.is_empty() {
+        vec![Api::all(client.clone())]
+    } else {
+        namespaces
+            .iter()
+            .map(|namespace| Api::namespaced(client.clone(), namespace))
+            .collect()
+    };
+    let mut uids = BTreeSet::new();
+    for projects in project_apis {
+        uids.extend(
+            projects
+                .list_metadata(&ListParams::default())
+                .await?
+                .items
+                .into_iter()
+                .filter_map(|project| project.metadata.uid),
+        );
+    }
+
+    let all = ListParams::default();
+    let mut objects = labelled::<Namespace>(client.clone(), all.clone()).await?;
+    //the operator may only list its pull secrets
+    let pull_secrets = all
+        .clone()
+        .fields(&format!("metadata.name={PULL_SECRET_NAME}"));
+    objects.extend(labelled::<Secret>(client.clone(), pull_secrets).await?);
+    objects.extend(labelled::<RoleBinding>(client.clone(), all.clone()).await?);
+    objects.extend(labelled::<ResourceQuota>(client.clone(), all.clone()).await?);
+    objects.extend(labelled::<LimitRange>(client.clone(), all.clone()).await?);
+    objects.extend(labelled::<NetworkPolicy>(client, all).await?);
+    Ok(orphans(&objects, &uids, namespaces))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner() -> ProjectOwner {
+        ProjectOwner {
+            name: "demo".to_string(),
+            namespace: "projects".to_string(),
+            uid: "4f1c".to_string(),
+        }
+    }
+
+    fn metadata(namespace: Option<&str>) -> ObjectMeta {
+        ObjectMeta {
+            name: Some("kyotu-project-quota".to_string()),
+            namespace: namespace.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_owner_references_only_in_project_namespace() {
+        let mut child = metadata(Some("projects"));
+        owner().own(&mut child);
+        assert!(owner().labelled(&child));
+        let references = child.owner_references.unwrap();
+        assert_eq!(references[0].kind, "Project");
+        assert_eq!(references[0].api_version, "kyotu.tech/v1");
+        assert_eq!(references[0].uid, "4f1c");
+
+        //cross-namespace and cluster scoped objects are only labelled
+        for namespace in [Some("demo-dev"), None] {
+            let mut object = metadata(namespace);
+            owner().own(&mut object);
+            assert!(owner().labelled(&object));
+            assert!(object.owner_references.is_none());
+        }
+        assert!(owner().metadata_patch(Some("demo-dev"))["metadata"]
+            .get("ownerReferences")
+            .is_none());
+    }
+
+    #[test]
+    fn test_project_ref() {
+        assert!(project_ref(&metadata(Some("demo-dev"))).is_none());
+        let mut owned = metadata(Some("demo-dev"));
+        owner().own(&mut owned);
+        let project = project_ref(&owned).unwrap();
+        assert_eq!(project.name, "demo");
+        assert_eq!(project.namespace.as_deref(), Some("projects"));
+    }
+
+    #[test]
+    fn test_orphans() {
+        let mut owned = metadata(Some("demo-dev"));
+        owner().own(&mut owned);
+        let objects = vec![
+            ("ResourceQuota".to_string(), owned),
+            ("ResourceQuota".to_string(), metadata(Some("other"))),
+        ];
+        assert!(orphans(&objects, &BTreeSet::from(["4f1c".to_string()]), &[]).is_empty());
+        assert!(orphans(&objects, &BTreeSet::new(), &["bu2-projects".to_string()]).is_empty());
+
+        let found = orphans(&objects, &BTreeSet::new(), &["projects".to_string()]);
+        assert_eq!(
+            found,
+            vec![Orphan {
+                kind: "ResourceQuota".to_string(),
+                namespace: Some("demo-dev".to_string()),
+                name: "kyotu-project-quota".to_string(),
+                project: "projects/demo".to_string(),
+            }]
+        );
+    }
+}
@@ -1,18 +1,24 @@
 use std::path::Path;
-use tera::{Context, Tera};
+use tera::Context;
 
-use crate::repository::Repository;
+use crate::credentials::GitCredentials;
+use crate::layout::{ProjectLayout, ProjectVars};
+use crate::project_crd::GitOpsCommit;
+use crate::repository::{Changes, KnownHosts, Outcome, Plan, Repository};
+use crate::templates::Templates;
 
-pub async fn create_project(name: &str, repo_root: &Path) -> Result<String, ProjectError> {
-    let tera = match Tera::new("templates/*.yaml") {
-        Ok(t) => t,
-        Err(e) => {
-            log::error!("Could not create Tera template: {}", e);
-            ::std::process::exit(1);
-        }
-    };
-    let mut context = Context::new();
-    context.insert("project_name", &name);
+pub async fn create_project(
+    project: &ProjectVars,
+    repo_root: &Path,
+    layout: &ProjectLayout,
+    templates: &Templates,
+    credentials: &GitCredentials,
+    dry_run: bool,
+) -> Result<Outcome, ProjectError> {
+    let name = project.project_name.as_str();
+    let mut context = Context::from_serialize(project)
+        .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
+    context.insert("project_dir", &layout.project_dir.to_string_lossy());
 
     let repo_url = match std::env::var("ARGO_REPO") {
         Ok(url) => url,
@@ -29,22 +35,26 @@ pub async fn create_project(name: &str, repo_root: &Path) -> Result<String, Proj
         }
     };
 
-    let deploy_token = std::env::var("ARGO_DEPLOY_TOKEN").expect("ARGO_DEPLOY_TOKEN not set");
     //clear tmp dir
     if repo_root.exists() {
         std::fs::remove_dir_all(repo_root).expect("Failed to remove repo root");
     }
+    credentials
+        .refresh()
+        .await
+        .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
     //clone repo into project folder
     let argo_repository = Repository::clone(
         &repo_url,
         &repo_branch,
         &repo_root.to_string_lossy(),
-        Some(&deploy_token),
+        credentials,
+        KnownHosts::from_env().map_err(|e| ProjectError::CreateProjectError(e.to_string()))?,
     )
-    .expect("Failed to clone repo");
+    .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
 
     //create project folder in repo_root
-    let project_path = Path::new(&repo_root).join("manifests").join(name);
+    let project_path = Path::new(&repo_root).join(&layout.project_dir);
     match std::fs::create_dir_all(&project_path) {
         Ok(_) => {
             log::info!("Created project folder {}", project_path.to_string_lossy());
@@ -62,30 +72,60 @@ pub async fn create_project(name: &str, repo_root: &Path) -> Result<String, Proj
             )));
         }
     }
+    let mut changes = Changes::default();
     //create .gitkeep file in project folder
+    changes.write(repo_root, layout.project_dir.join(".gitkeep"));
     let gitkeep_path = project_path.join(".gitkeep");
     std::fs::File::create(gitkeep_path).expect("Could not create .gitkeep file");
     //create project.yaml file in project folder
-    let project_yaml_path = Path::new(&repo_root)
-        .join("applications")
-        .join(format!("{name}.yaml"));
-    let mut file =
-        std::fs::File::create(project_yaml_path).expect("Could not create project.yaml file");
-    tera.render_to("argo_tmpl.yaml", &context, &mut file)
-        .expect("Could not render project.yaml file");
+    let project_yaml = layout.application.clone();
+    if let Some(parent) = project_yaml.parent() {
+        std::fs::create_dir_all(repo_root.join(parent))
+            .expect("Could not create application folder");
+    }
+    changes.write(repo_root, project_yaml.clone());
+    let project_yaml_path = Path::new(&repo_root).join(&project_yaml);
+    let rendered = templates
+        .render("argo_tmpl.yaml", &context)
+        .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
+    std::fs::write(project_yaml_path, rendered).expect("Could not create project.yaml file");
+
+    if dry_run {
+        let diff = argo_repository
+            .diff(&changes)
+            .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
+        return Ok(Outcome::Planned(Plan {
+            repo: repo_url,
+            branch: repo_branch,
+            diff,
+        }));
+    }
 
     //commit and push changes
-    argo_repository
-        .commit(format!("Created project {name}").as_str())
-        .expect("Failed to commit changes");
-    argo_repository
-        .push(&repo_branch)
-        .expect("Failed to push changes");
+    let commit = argo_repository
+        .commit(format!("Created project {name}").as_str(), &changes)
+        .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
+    if commit.is_some() {
+        argo_repository
+            .push(&repo_branch)
+            .map_err(|e| ProjectError::CreateProjectError(e.to_string()))?;
+    }
 
-    Ok(format!("Created project {name}"))
+    Ok(Outcome::Committed(GitOpsCommit {
+        repo: repo_url,
+        branch: repo_branch,
+        commit_sha: commit,
+        path: project_yaml.to_string_lossy().to_string(),
+    }))
 }
 
-pub async fn delete_project(name: &str, repo_root: &Path) -> Result<String, ProjectError> {
+pub async fn delete_project(
+    name: &str,
+    repo_root: &Path,
+    layout: &ProjectLayout,
+    credentials: &GitCredentials,
+    dry_run: bool,
+) -> Result<Outcome, ProjectError> {
     let repo_url = match std::env::var("ARGO_REPO") {
         Ok(url) => url,
         Err(e) => {
@@ -104,18 +144,24 @@ pub async fn delete_project(name: &str, repo_root: &Path) -> Result<String, Proj
     if repo_root.exists() {
         std::fs::remove_dir_all(repo_root).expect("Failed to remove repo root");
     }
+    credentials
+        .refresh()
+        .await
+        .map_err(|e| ProjectError::DeleteProjectError(e.to_string()))?;
 
-    let deploy_token = std::env::var("ARGO_DEPLOY_TOKEN").expect("ARGO_DEPLOY_TOKEN not set");
     //clone repo into project folder
     let argo_repository = Repository::clone(
         &repo_url,
         &repo_branch,
         &repo_root.to_string_lossy(),
-        Some(&deploy_token),
+        credentials,
+        KnownHosts::from_env().map_err(|e| ProjectError::DeleteProjectError(e.to_string()))?,
     )
-    .expect("Failed to clone repo");
+    .map_err(|e| ProjectError::DeleteProjectError(e.to_string()))?;
 
-    let project_path = Path::new(&repo_root).join("manifests").join(name);
+    let mut changes = Changes::default();
+    changes.delete(layout.project_dir.clone());
+    let project_path = Path::new(&repo_root).join(&layout.project_dir);
     match std::fs::remove_dir_all(&project_path) {
         Ok(_) => {
             log::info!("Deleted project folder {}", project_path.to_string_lossy());
@@ -134,20 +180,38 @@ pub async fn delete_project(name: &str, repo_root: &Path) -> Result<String, Proj
         }
     }
 
-    let project_yaml_path = Path::new(&repo_root)
-        .join("applications")
-        .join(format!("{name}.yaml"));
+    let project_yaml = layout.application.clone();
+    changes.delete(project_yaml.clone());
+    let project_yaml_path = Path::new(&repo_root).join(&project_yaml);
     std::fs::remove_file(project_yaml_path)
         .unwrap_or_else(|_| panic!("Could not delete {name}.yaml file"));
 
+    if dry_run {
+        let diff = argo_repository
+            .diff(&changes)
+            .map_err(|e| ProjectError::DeleteProjectError(e.to_string()))?;
+        return Ok(Outcome::Planned(Plan {
+            repo: repo_url,
+            branch: repo_branch,
+            diff,
+        }));
+    }
+
     //commit and push changes
-    argo_repository
-        .commit(format!("Deleted project {name}").as_str())
-        .expect("Failed to commit changes");
-    argo_repository
-        .push(&repo_branch)
-        .expect("Failed to push changes");
-    Ok(format!("Deleted project {name}"))
+    let commit = argo_repository
+        .commit(format!("Deleted project {name}").as_str(), &changes)
+        .map_err(|e| ProjectError::DeleteProjectError(e.to_string()))?;
+    if commit.is_some() {
+        argo_repository
+            .push(&repo_branch)
+            .map_err(|e| ProjectError::DeleteProjectError(e.to_string()))?;
+    }
+    Ok(Outcome::Committed(GitOpsCommit {
+        repo: repo_url,
+        branch: repo_branch,
+        commit_sha: commit,
+        path: project_yaml.to_string_lossy().to_string(),
+    }))
 }
 
 //error enum
@@ -2,6 +2,7 @@ use kube::CustomResource;
 use lazy_static::lazy_static;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use validator::Validate;
 
 lazy_static! {
@@ -16,6 +17,7 @@ lazy_static! {
     kind = "Project",
     plural = "projects",
     derive = "PartialEq",
+    status = "ProjectStatus",
     namespaced
 )]
 #[serde(rename_all = "camelCase")]
@@ -24,4 +26,172 @@ pub struct ProjectSpec {
     #[validate(regex = "RE_ENV_TYPE")]
     pub environment_type: String,
     pub google_group: String,
+    /// Vault access granted on top of the environment policy template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault: Option<VaultSpec>,
+    /// ResourceQuota and LimitRange of the namespace, the environment profile when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaSpec>,
+    /// NetworkPolicies of the namespace, the namespace is left open when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkSpec>,
+    /// Labels and annotations of the namespace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<NamespaceSpec>,
+    /// Take ownership of a namespace that already exists instead of reporting a conflict
+    #[serde(default)]
+    pub adopt_existing_namespace: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultSpec {
+    /// Extra policy paths, such as `transit/encrypt/<key>` or `database/creds/<role>`
+    #[serde(default)]
+    pub paths: Vec<VaultPath>,
+    /// Service accounts of the project namespace allowed to log in with the Kubernetes auth role, all when empty
+    #[serde(default)]
+    pub service_accounts: Vec<String>,
+    /// KV secrets created empty under the project secret path when missing, such as `app`
+    #[serde(default)]
+    pub seed_secrets: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultPath {
+    pub path: String,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaSpec {
+    /// Preset sizes, `large` for prod, `medium` for stage and `small` otherwise when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<QuotaProfile>,
+    /// ResourceQuota hard limits, replacing the values of the profile, such as `requests.cpu: "4"`
+    #[serde(default)]
+    pub hard: BTreeMap<String, String>,
+    /// LimitRange container limits, replacing the defaults of the profile
+    #[serde(default)]
+    pub default_limits: BTreeMap<String, String>,
+    /// LimitRange container requests, replacing the defaults of the profile
+    #[serde(default)]
+    pub default_requests: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaProfile {
+    Small,
+    Medium,
+    Large,
+    /// Only the values set in the spec
+    Custom,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceSpec {
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkSpec {
+    #[serde(default)]
+    pub profile: NetworkProfile,
+    /// Namespaces allowed to reach the project pods on top of the ingress controller and monitoring
+    #[serde(default)]
+    pub allowed_namespaces: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkProfile {
+    /// Ingress only from the namespace, the ingress controller, monitoring and allowed namespaces
+    #[default]
+    Isolated,
+    /// No NetworkPolicies
+    Open,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gitops: Option<GitOpsStatus>,
+    /// Why the Project cannot manage its namespace, cleared once it can
+    #[serde(default)]
+    pub conflict: Option<String>,
+}
+
+/// Commits in the GitOps repositories that provisioned the project
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GitOpsStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argo: Option<GitOpsCommit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flux: Option<GitOpsCommit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GitOpsCommit {
+    pub repo: String,
+    pub branch: String,
+    /// None when the repository already contained the project and nothing was committed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_sha: Option<String>,
+    pub path: String,
+}
+
+impl GitOpsCommit {
+    //keep the sha of the previous commit when nothing had to be committed this time
+    pub fn or_previous(self, previous: Option<&GitOpsCommit>) -> Self {
+        match (self.commit_sha.as_ref(), previous) {
+            (None, Some(previous))
+                if previous.repo == self.repo && previous.branch == self.branch =>
+            {
+                Self {
+                    commit_sha: previous.commit_sha.clone(),
+                    ..self
+                }
+            }
+            _ => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(branch: &str, sha: Option<&str>) -> GitOpsCommit {
+        GitOpsCommit {
+            repo: "git@github.com:Kyotu-Technology/flux.git".to_string(),
+            branch: branch.to_string(),
+            commit_sha: sha.map(str::to_string),
+            path: "applications/test-dev.yaml".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_or_previous() {
+        let previous = commit("main", Some("abc"));
+
+        let new = commit("main", Some("def")).or_previous(Some(&previous));
+        assert_eq!(new.commit_sha.as_deref(), Some("def"));
+
+        let unchanged = commit("main", None).or_previous(Some(&previous));
+        assert_eq!(unchanged.commit_sha.as_deref(), Some("abc"));
+
+        let other_branch = commit("test", None).or_previous(Some(&previous));
+        assert_eq!(other_branch.commit_sha, None);
+    }
 }
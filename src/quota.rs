@@ -0,0 +1,290 @@
+use k8s_openapi::api::core::v1::{
+    LimitRange, LimitRangeItem, LimitRangeSpec, ResourceQuota, ResourceQuotaSpec,
+};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams};
+use kube::{Api, Client};
+use std::collections::BTreeMap;
+
+use crate::owner::ProjectOwner;
+use crate::project_crd::{QuotaProfile, QuotaSpec};
+
+/// Name of the ResourceQuota and LimitRange created in the project namespace
+pub const QUOTA_NAME: &str = "kyotu-project-quota";
+
+/// Resources of a quota profile
+struct Preset {
+    hard: [(&'static str, &'static str); 6],
+    default_limits: [(&'static str, &'static str); 2],
+    default_requests: [(&'static str, &'static str); 2],
+}
+
+const SMALL: Preset = Preset {
+    hard: [
+        ("requests.cpu", "2"),
+        ("requests.memory", "4Gi"),
+        ("limits.cpu", "4"),
+        ("limits.memory", "8Gi"),
+        ("pods", "20"),
+        ("persistentvolumeclaims", "5"),
+    ],
+    default_limits: [("cpu", "500m"), ("memory", "512Mi")],
+    default_requests: [("cpu", "100m"), ("memory", "128Mi")],
+};
+
+const MEDIUM: Preset = Preset {
+    hard: [
+        ("requests.cpu", "4"),
+        ("requests.memory", "8Gi"),
+        ("limits.cpu", "8"),
+        ("limits.memory", "16Gi"),
+        ("pods", "50"),
+        ("persistentvolumeclaims", "10"),
+    ],
+    default_limits: [("cpu", "1"), ("memory", "1Gi")],
+    default_requests: [("cpu", "200m"), ("memory", "256Mi")],
+};
+
+const LARGE: Preset = Preset {
+    hard: [
+        ("requests.cpu", "8"),
+        ("requests.memory", "16Gi"),
+        ("limits.cpu", "16"),
+        ("limits.memory", "32Gi"),
+        ("pods", "100"),
+        ("persistentvolumeclaims", "20"),
+    ],
+    default_limits: [("cpu", "2"), ("memory", "2Gi")],
+    default_requests: [("cpu", "500m"), ("memory", "512Mi")],
+};
+
+/// Resources applied to a project namespace
+#[derive(Debug, PartialEq)]
+pub struct Quota {
+    pub hard: BTreeMap<String, String>,
+    pub default_limits: BTreeMap<String, String>,
+    pub default_requests: BTreeMap<String, String>,
+}
+
+impl Quota {
+    //profile of the spec or of the environment, with the values set in the spec on top
+    pub fn from_spec(spec: Option<&QuotaSpec>, environment_type: &str) -> Result<Self, QuotaError> {
+        let default_spec = QuotaSpec::default();
+        let spec = spec.unwrap_or(&default_spec);
+        let profile = spec.profile.unwrap_or(match environment_type {
+            "prod" => QuotaProfile::Large,
+            "stage" => QuotaProfile::Medium,
+            _ => QuotaProfile::Small,
+        });
+        let mut quota = match profile {
+            QuotaProfile::Small => Self::from_preset(&SMALL),
+            QuotaProfile::Medium => Self::from_preset(&MEDIUM),
+            QuotaProfile::Large => Self::from_preset(&LARGE),
+            QuotaProfile::Custom if spec.hard.is_empty() => {
+                return Err(QuotaError::InvalidQuota(
+                    "the custom profile needs hard limits".to_string(),
+                ))
+            }
+            QuotaProfile::Custom => Self {
+                hard: BTreeMap::new(),
+                default_limits: BTreeMap::new(),
+                default_requests: BTreeMap::new(),
+            },
+        };
+        quota.hard.extend(spec.hard.clone());
+        quota.default_limits.extend(spec.default_limits.clone());
+        quota.default_requests.extend(spec.default_requests.clone());
+        Ok(quota)
+    }
+
+    fn from_preset(preset: &Preset) -> Self {
+        let to_map = |values: &[(&str, &str)]| {
+            values
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        Self {
+            hard: to_map(&preset.hard),
+            default_limits: to_map(&preset.default_limits),
+            default_requests: to_map(&preset.default_requests),
+        }
+    }
+
+    pub fn resource_quota(&self, namespace: &str) -> ResourceQuota {
+        ResourceQuota {
+            metadata: metadata(namespace),
+            spec: Some(ResourceQuotaSpec {
+                hard: Some(quantities(&self.hard)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    //limit range with container defaults, none when neither limits nor requests are set
+    pub fn limit_range(&self, namespace: &str) -> Option<LimitRange> {
+        if self.default_limits.is_empty() && self.default_requests.is_empty() {
+            return None;
+        }
+        Some(LimitRange {
+            metadata: metadata(namespace),
+            spec: Some(LimitRangeSpec {
+                limits: vec![LimitRangeItem {
+                    type_: "Container".to_string(),
+                    default: Some(quantities(&self.default_limits))
+                        .filter(|limits| !limits.is_empty()),
+                    default_request: Some(quantities(&self.default_requests))
+                        .filter(|requests| !requests.is_empty()),
+                    ..Default::default()
+                }],
+            }),
+        })
+    }
+}
+
+fn metadata(namespace: &str) -> ObjectMeta {
+    let mut labels: BTreeMap<String, String> = BTreeMap::new();
+    labels.insert("app".to_string(), "kyotu-project-operator".to_string());
+    ObjectMeta {
+        name: Some(QUOTA_NAME.to_string()),
+        namespace: Some(namespace.to_string()),
+        labels: Some(labels),
+        ..Default::default()
+    }
+}
+
+fn quantities(values: &BTreeMap<String, String>) -> BTreeMap<String, Quantity> {
+    values
+        .iter()
+        .map(|(name, value)| (name.clone(), Quantity(value.clone())))
+        .collect()
+}
+
+//apply quota and limit range, overwriting changes made outside of the Project
+pub async fn apply_quota(
+    client: Client,
+    namespace: &str,
+    quota: &Quota,
+    owner: &ProjectOwner,
+) -> anyhow::Result<String> {
+    let params = PatchParams::apply("kyotu-project-operator").force();
+
+    let quota_api: Api<ResourceQuota> = Api::namespaced(client.clone(), namespace);
+    let mut resource_quota = quota.resource_quota(namespace);
+    owner.own(&mut resource_quota.metadata);
+    quota_api
+        .patch(QUOTA_NAME, &params, &Patch::Apply(&resource_quota))
+        .await?;
+
+    let limit_api: Api<LimitRange> = Api::namespaced(client, namespace);
+    match quota.limit_range(namespace) {
+        Some(mut limit_range) => {
+            owner.own(&mut limit_range.metadata);
+            limit_api
+                .patch(QUOTA_NAME, &params, &Patch::Apply(&limit_range))
+                .await?;
+        }
+        None => {
+            if limit_api.get_opt(QUOTA_NAME).await?.is_some() {
+                limit_api
+                    .delete(QUOTA_NAME, &DeleteParams::default())
+                    .await?;
+            }
+        }
+    }
+    log::info!("Applied quota in namespace {}", namespace);
+    Ok(namespace.to_string())
+}
+
+//delete quota and limit range
+pub async fn delete_quota(client: Client, namespace: &str) -> anyhow::Result<String> {
+    let quota_api: Api<ResourceQuota> = Api::namespaced(client.clone(), namespace);
+    if quota_api.get_opt(QUOTA_NAME).await?.is_some() {
+        quota_api
+            .delete(QUOTA_NAME, &DeleteParams::default())
+            .await?;
+    }
+    let limit_api: Api<LimitRange> = Api::namespaced(client, namespace);
+    if limit_api.get_opt(QUOTA_NAME).await?.is_some() {
+        limit_api
+            .delete(QUOTA_NAME, &DeleteParams::default())
+            .await?;
+    }
+    log::info!("Deleted quota in namespace {}", namespace);
+    Ok(namespace.to_string())
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum QuotaError {
+    #[error("Invalid quota: {0}")]
+    InvalidQuota(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_profiles() {
+        let prod = Quota::from_spec(None, "prod").unwrap();
+        assert_eq!(prod.hard["limits.memory"], "32Gi");
+        let dev = Quota::from_spec(None, "dev").unwrap();
+        assert_eq!(dev.hard["pods"], "20");
+        let medium = QuotaSpec {
+            profile: Some(QuotaProfile::Medium),
+            ..Default::default()
+        };
+        assert_eq!(
+            Quota::from_spec(Some(&medium), "prod").unwrap().hard["pods"],
+            "50"
+        );
+    }
+
+    #[test]
+    fn test_spec_values_override_profile() {
+        let spec = QuotaSpec {
+            hard: BTreeMap::from([("pods".to_string(), "5".to_string())]),
+            default_limits: BTreeMap::from([("cpu".to_string(), "250m".to_string())]),
+            ..Default::default()
+        };
+        let quota = Quota::from_spec(Some(&spec), "dev").unwrap();
+        assert_eq!(quota.hard["pods"], "5");
+        assert_eq!(quota.hard["limits.cpu"], "4");
+        assert_eq!(quota.default_limits["cpu"], "250m");
+        assert_eq!(quota.default_limits["memory"], "512Mi");
+
+        let limit_range = quota.limit_range("demo-dev").unwrap();
+        let item = &limit_range.spec.unwrap().limits[0];
+        assert_eq!(item.type_, "Container");
+        assert_eq!(
+            item.default.as_ref().unwrap()["cpu"],
+            Quantity("250m".to_string())
+        );
+    }
+
+    #[test]
+    fn test_custom_profile() {
+        let spec = QuotaSpec {
+            profile: Some(QuotaProfile::Custom),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Quota::from_spec(Some(&spec), "dev"),
+            Err(QuotaError::InvalidQuota(_))
+        ));
+
+        let spec = QuotaSpec {
+            hard: BTreeMap::from([("requests.cpu".to_string(), "1".to_string())]),
+            ..spec
+        };
+        let quota = Quota::from_spec(Some(&spec), "dev").unwrap();
+        assert_eq!(quota.hard.len(), 1);
+        assert!(quota.limit_range("demo-dev").is_none());
+        assert_eq!(
+            quota.resource_quota("demo-dev").spec.unwrap().hard.unwrap()["requests.cpu"],
+            Quantity("1".to_string())
+        );
+    }
+}
@@ -1,60 +1,26 @@
-use crate::repository::Repository;
-use serde::{Deserialize, Serialize};
+use crate::argo_rbac::{parse_rules, RbacManifest};
+use crate::credentials::GitCredentials;
+use crate::layout::{ProjectLayout, ProjectVars};
+use crate::project_crd::{GitOpsCommit, VaultSpec};
+use crate::repository::{Changes, KnownHosts, Outcome, Plan, Repository};
+use crate::templates::Templates;
+use crate::vault::{VaultAccess, VaultBackend};
+use crate::vault_policy;
 use std::path::Path;
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct VaultConfig {
-    vault: Vault,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-struct Vault {
-    external_config: ExternalConfig,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-struct ExternalConfig {
-    policies: Vec<Policy>,
-    groups: Vec<Group>,
-    #[serde(rename = "group-aliases")]
-    group_aliases: Vec<GroupAlias>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Policy {
-    name: String,
-    rules: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-struct Group {
-    name: String,
-    policies: Vec<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    metadata: Option<Metadata>,
-    #[serde(rename = "type")]
-    group_type: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Metadata {
-    privileged: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct GroupAlias {
-    name: String,
-    mountpath: String,
-    group: String,
-}
+use tera::Context;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn add_rbacs(
-    name: &str,
+    project: &ProjectVars,
+    vault_spec: &VaultSpec,
     repo_root: &Path,
-    google_group: &str,
-) -> Result<String, RbacError> {
+    layout: &ProjectLayout,
+    templates: &Templates,
+    vault: &dyn VaultBackend,
+    credentials: &GitCredentials,
+    dry_run: bool,
+) -> Result<Outcome, RbacError> {
+    let name = project.project_name.as_str();
     let repo_url = match std::env::var("FLUX_REPO") {
         Ok(url) => url,
         Err(e) => {
@@ -74,141 +40,97 @@ pub async fn add_rbacs(
     if repo_root.exists() {
         std::fs::remove_dir_all(repo_root).expect("Failed to remove repo root");
     }
-
-    let deploy_token = std::env::var("FLUX_DEPLOY_TOKEN").expect("FLUX_DEPLOY_TOKEN not set");
+    credentials
+        .refresh()
+        .await
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
 
     //clone repo into project folder
     let flux_repository = Repository::clone(
         &repo_url,
         &repo_branch,
         &repo_root.to_string_lossy(),
-        Some(&deploy_token),
-    )
-    .expect("Failed to clone repo");
-
-    let vault_values = std::fs::read_to_string(format!(
-        "{}/namespaces/vault/vault/rbac_values.yaml",
-        repo_root.to_string_lossy()
-    ))
-    .expect("Something went wrong reading the file");
-
-    let mut vault_values: VaultConfig = serde_yaml::from_str(&vault_values).unwrap();
-
-    //create new policy
-    let policy_name = format!("{}_access", name.replace('-', "_"));
-    let new_policy = Policy{
-        name: policy_name.clone(),
-        rules: format!(
-            "path \"secret/{}/*\" {{\n  capabilities = [\"create\", \"read\", \"update\", \"delete\", \"list\"]\n}}",
-            name.replace('-', "_")
-        ),
-    };
-
-    //check if policy already exists
-    let mut policy_exists = false;
-    for policy in vault_values.vault.external_config.policies.iter() {
-        if policy.name == new_policy.name {
-            policy_exists = true;
-        }
-    }
-    //add new policy to vault_values
-    if !policy_exists {
-        vault_values.vault.external_config.policies.push(new_policy);
-    }
-
-    //check if group already exists if it does add policy to group else create new group
-    let mut group_exists = false;
-    for group in vault_values.vault.external_config.groups.iter_mut() {
-        if group.name == google_group {
-            group_exists = true;
-            //add policy to group if it doesn't already exist
-            if !group.policies.contains(&policy_name) {
-                group.policies.push(policy_name.clone());
-            }
-        }
-    }
-    //add new group to vault_values
-    if !group_exists {
-        let new_group = Group {
-            name: google_group.to_string(),
-            policies: vec![policy_name],
-            metadata: None,
-            group_type: "external".to_string(),
-        };
-        vault_values.vault.external_config.groups.push(new_group);
-    }
-
-    //check if group alias exists if not create new group alias
-    let mut group_alias_exists = false;
-    for group_alias in vault_values.vault.external_config.group_aliases.iter() {
-        if group_alias.name == google_group {
-            group_alias_exists = true;
-        }
-    }
-    //add new group alias to vault_values
-    if !group_alias_exists {
-        let new_group_alias = GroupAlias {
-            name: google_group.to_string(),
-            mountpath: "oidc".to_string(),
-            group: google_group.to_string(),
-        };
-        vault_values
-            .vault
-            .external_config
-            .group_aliases
-            .push(new_group_alias);
-    }
-
-    //write vault_values yaml back to file
-    std::fs::write(
-        format!(
-            "{}/namespaces/vault/vault/rbac_values.yaml",
-            repo_root.to_string_lossy()
-        ),
-        serde_yaml::to_string(&vault_values).unwrap(),
+        credentials,
+        KnownHosts::from_env().map_err(|e| RbacError::CreateRbacError(e.to_string()))?,
     )
-    .expect("Unable to write file");
+    .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+
+    //add policies, group, group alias and the workload auth role
+    let mut access = VaultAccess::for_project(project, vault_spec);
+    access.policy = vault_policy::render(templates, project, &vault_spec.paths)
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    access.workload_policy = vault_policy::render_workload(templates, project, &vault_spec.paths)
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    let mut modified = vault
+        .grant(repo_root, layout, &access, dry_run)
+        .await
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
 
     //argo rbac
-    let mut argo_values = std::fs::read_to_string(format!(
-        "{}/namespaces/argocd/argocd-operator/rbac.yaml",
-        repo_root.to_string_lossy()
-    ))
-    .expect("Something went wrong reading the file");
-
-    let template = std::fs::read_to_string("templates/rbac_tmpl.yaml")
+    let argo_values = std::fs::read_to_string(repo_root.join(&layout.argo_rbac))
         .expect("Something went wrong reading the file");
 
-    let template = template.replace("{{ name }}", name);
-    let template = template.replace("{{ google_group }}", google_group);
-
-    argo_values.push_str(format!("\n{template}").as_str());
+    let template = templates
+        .render("rbac_tmpl.yaml", &rbac_context(project))
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+
+    let mut argo_rbac =
+        RbacManifest::parse(&argo_values).map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    let mut policy = argo_rbac
+        .policy()
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    policy.set_project(name, parse_rules(&template));
+    argo_rbac
+        .set_policy(&policy)
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    let argo_values = argo_rbac.to_yaml();
 
     //write argo_values yaml back to file
-    std::fs::write(
-        format!(
-            "{}/namespaces/argocd/argocd-operator/rbac.yaml",
-            repo_root.to_string_lossy()
-        ),
-        argo_values,
-    )
-    .unwrap();
+    std::fs::write(repo_root.join(&layout.argo_rbac), argo_values).unwrap();
+
+    modified.push(layout.argo_rbac.clone());
+    let changes = Changes {
+        modified,
+        ..Default::default()
+    };
+    if dry_run {
+        let diff = flux_repository
+            .diff(&changes)
+            .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+        return Ok(Outcome::Planned(Plan {
+            repo: repo_url,
+            branch: repo_branch,
+            diff,
+        }));
+    }
 
-    flux_repository
-        .commit(format!("Created rbac for {name}").as_str())
-        .expect("Failed to commit changes");
-    flux_repository
-        .push(&repo_branch)
-        .expect("Failed to push changes");
+    //commit and push changes
+    let commit = flux_repository
+        .commit(format!("Created rbac for {name}").as_str(), &changes)
+        .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    if commit.is_some() {
+        flux_repository
+            .push(&repo_branch)
+            .map_err(|e| RbacError::CreateRbacError(e.to_string()))?;
+    }
 
-    Ok(format!("Added rbacs for project {name}"))
+    Ok(Outcome::Committed(GitOpsCommit {
+        repo: repo_url,
+        branch: repo_branch,
+        commit_sha: commit,
+        path: layout.argo_rbac.to_string_lossy().to_string(),
+    }))
 }
 
 pub async fn remove_rbacs(
-    name: &str,
+    project: &ProjectVars,
     repo_root: &Path,
-    google_group: &str,
-) -> Result<String, RbacError> {
+    layout: &ProjectLayout,
+    vault: &dyn VaultBackend,
+    credentials: &GitCredentials,
+    dry_run: bool,
+) -> Result<Outcome, RbacError> {
+    let name = project.project_name.as_str();
     let repo_url = match std::env::var("FLUX_REPO") {
         Ok(url) => url,
         Err(e) => {
@@ -229,121 +151,91 @@ pub async fn remove_rbacs(
     if repo_root.exists() {
         std::fs::remove_dir_all(repo_root).expect("Failed to remove repo root");
     }
-
-    let deploy_token = std::env::var("FLUX_DEPLOY_TOKEN").expect("FLUX_DEPLOY_TOKEN not set");
+    credentials
+        .refresh()
+        .await
+        .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
 
     let flux_repository = Repository::clone(
         &repo_url,
         &repo_branch,
         &repo_root.to_string_lossy(),
-        Some(&deploy_token),
+        credentials,
+        KnownHosts::from_env().map_err(|e| RbacError::DeleteRbacError(e.to_string()))?,
     )
-    .expect("Failed to clone repo");
-
-    let vault_values = std::fs::read_to_string(format!(
-        "{}/namespaces/vault/vault/rbac_values.yaml",
-        repo_root.to_string_lossy()
-    ))
-    .expect("Something went wrong reading the file");
-
-    let mut vault_values: VaultConfig = serde_yaml::from_str(&vault_values).unwrap();
-
-    //remove policy from vault_values
-
-    let policy_name = format!("{}_access", name.replace('-', "_"));
-    vault_values
-        .vault
-        .external_config
-        .policies
-        .retain(|policy| policy.name != policy_name);
-
-    //remove policy from all groups
-    for group in vault_values.vault.external_config.groups.iter_mut() {
-        group.policies.retain(|policy| policy != &policy_name);
-    }
-
-    //remove groups with no policies
-    vault_values
-        .vault
-        .external_config
-        .groups
-        .retain(|group| !group.policies.is_empty());
+    .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
 
-    //remove group alias if none group with google_group exists
-    let mut group_exists = false;
-    for group in vault_values.vault.external_config.groups.iter() {
-        if group.name == google_group {
-            group_exists = true;
-        }
-    }
-
-    if !group_exists {
-        vault_values
-            .vault
-            .external_config
-            .group_aliases
-            .retain(|group_alias| group_alias.name != google_group);
-    }
-    //write vault_values yaml back to file
-    std::fs::write(
-        format!(
-            "{}/namespaces/vault/vault/rbac_values.yaml",
-            repo_root.to_string_lossy()
-        ),
-        serde_yaml::to_string(&vault_values).unwrap(),
-    )
-    .expect("Unable to write file");
+    //remove policies, groups left without policies, their alias and the workload auth role
+    let access = VaultAccess::for_project(project, &VaultSpec::default());
+    let mut modified = vault
+        .revoke(repo_root, layout, &access, dry_run)
+        .await
+        .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
 
     //argo rbac
-
-    let argo_values = std::fs::read_to_string(format!(
-        "{}/namespaces/argocd/argocd-operator/rbac.yaml",
-        repo_root.to_string_lossy()
-    ))
-    .expect("Something went wrong reading the file");
-
-    let template = std::fs::read_to_string("templates/rbac_tmpl.yaml")
+    let argo_values = std::fs::read_to_string(repo_root.join(&layout.argo_rbac))
         .expect("Something went wrong reading the file");
 
-    let template = template.replace("{{ name }}", name);
-    let template = template.replace("{{ google_group }}", google_group);
+    let mut argo_rbac =
+        RbacManifest::parse(&argo_values).map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
+    let mut policy = argo_rbac
+        .policy()
+        .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
+    policy.remove_project(name);
+    argo_rbac
+        .set_policy(&policy)
+        .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
+    let argo_values = argo_rbac.to_yaml();
 
-    //remove template from rbac.yaml line by line
-    let mut argo_values_lines = argo_values.lines().collect::<Vec<&str>>();
-    let template_lines = template.lines().collect::<Vec<&str>>();
+    //write argo_values yaml back to file
+    std::fs::write(repo_root.join(&layout.argo_rbac), argo_values).unwrap();
 
-    for line in template_lines.iter() {
-        argo_values_lines.retain(|argo_line| argo_line != line);
+    modified.push(layout.argo_rbac.clone());
+    let changes = Changes {
+        modified,
+        ..Default::default()
+    };
+    if dry_run {
+        let diff = flux_repository
+            .diff(&changes)
+            .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
+        return Ok(Outcome::Planned(Plan {
+            repo: repo_url,
+            branch: repo_branch,
+            diff,
+        }));
     }
 
-    let argo_values = argo_values_lines.join("\n");
-
-    //write argo_values yaml back to file
-    std::fs::write(
-        format!(
-            "{}/namespaces/argocd/argocd-operator/rbac.yaml",
-            repo_root.to_string_lossy()
-        ),
-        argo_values,
-    )
-    .unwrap();
-
     //commit and push changes
-    flux_repository
-        .commit(format!("Removed rbac for {name}").as_str())
-        .expect("Failed to commit changes");
-    flux_repository
-        .push(&repo_branch)
-        .expect("Failed to push changes");
+    let commit = flux_repository
+        .commit(format!("Removed rbac for {name}").as_str(), &changes)
+        .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
+    if commit.is_some() {
+        flux_repository
+            .push(&repo_branch)
+            .map_err(|e| RbacError::DeleteRbacError(e.to_string()))?;
+    }
+
+    Ok(Outcome::Committed(GitOpsCommit {
+        repo: repo_url,
+        branch: repo_branch,
+        commit_sha: commit,
+        path: layout.argo_rbac.to_string_lossy().to_string(),
+    }))
+}
 
-    Ok(format!("Removed rbacs for project {name}"))
+//template context, name is kept for templates written before the full project context
+fn rbac_context(project: &ProjectVars) -> Context {
+    let mut context = Context::from_serialize(project).expect("Project variables serialize");
+    context.insert("name", &project.project_name);
+    context
 }
 
 //error enum
 #[derive(Debug, thiserror::Error)]
 pub enum RbacError {
     #[error("Could not create rbac: {0}")]
-    _CreateRbacError(String),
+    CreateRbacError(String),
     #[error("Could not delete rbac: {0}")]
-    _DeleteRbactError(String),
+    DeleteRbacError(String),
 }
@@ -1,12 +1,217 @@
-use git2::CredentialType;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::engine::Engine as _;
+use git2::cert::Cert;
+use git2::{CertificateCheckStatus, ErrorClass, ErrorCode};
+use serde::Serialize;
 use std::path::{Path, PathBuf};
 use tracing::log;
 
+use crate::credentials::GitCredentials;
+use crate::project_crd::GitOpsCommit;
+
 pub struct Repository {
     inner: git2::Repository,
     base_path: PathBuf,
-    deploy_key: Option<String>,
-    cred_type: CredentialType,
+    credentials: GitCredentials,
+    known_hosts: Option<KnownHosts>,
+    ssh_port: u16,
+}
+
+/// Paths, relative to the repository root, changed by the operator
+#[derive(Debug, Clone, Default)]
+pub struct Changes {
+    pub added: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+}
+
+impl Changes {
+    //record a file about to be written as added or modified, depending on whether it exists yet
+    pub fn write(&mut self, repo_root: &Path, path: PathBuf) {
+        if repo_root.join(&path).exists() {
+            self.modified.push(path);
+        } else {
+            self.added.push(path);
+        }
+    }
+
+    //record a file or directory about to be removed
+    pub fn delete(&mut self, path: PathBuf) {
+        self.deleted.push(path);
+    }
+}
+
+/// Unified diff of the changes the operator would push to a repository
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub repo: String,
+    pub branch: String,
+    pub diff: String,
+}
+
+/// Result of editing a GitOps repository: committed and pushed, or only planned in dry-run mode
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Committed(GitOpsCommit),
+    Planned(Plan),
+}
+
+impl Outcome {
+    pub fn commit(self) -> Option<GitOpsCommit> {
+        match self {
+            Outcome::Committed(commit) => Some(commit),
+            Outcome::Planned(_) => None,
+        }
+    }
+
+    pub fn plan(self) -> Option<Plan> {
+        match self {
+            Outcome::Committed(_) => None,
+            Outcome::Planned(plan) => Some(plan),
+        }
+    }
+}
+
+/// Pinned SSH host keys in OpenSSH `known_hosts` format
+///
+/// Plain (`host`) and bracketed (`[host]:port`) patterns are supported, as well as
+/// `@revoked` markers. Hashed hostnames and `@cert-authority` lines are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct KnownHosts {
+    entries: Vec<KnownHost>,
+}
+
+#[derive(Debug, Clone)]
+struct KnownHost {
+    patterns: Vec<String>,
+    key: Vec<u8>,
+    revoked: bool,
+}
+
+impl KnownHosts {
+    //parse known_hosts file contents
+    pub fn parse(contents: &str) -> Self {
+        let mut entries = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace().peekable();
+            let revoked = match fields.peek() {
+                Some(&"@revoked") => {
+                    fields.next();
+                    true
+                }
+                Some(marker) if marker.starts_with('@') => {
+                    log::warn!("Ignoring unsupported known_hosts marker {}", marker);
+                    continue;
+                }
+                _ => false,
+            };
+            let (Some(hosts), Some(_key_type), Some(key)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                log::warn!("Ignoring malformed known_hosts line: {}", line);
+                continue;
+            };
+            if hosts.starts_with('|') {
+                log::warn!("Ignoring hashed known_hosts entry");
+                continue;
+            }
+            let key = match BASE64.decode(key) {
+                Ok(key) => key,
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring known_hosts entry for {} with invalid key: {}",
+                        hosts,
+                        e
+                    );
+                    continue;
+                }
+            };
+            entries.push(KnownHost {
+                patterns: hosts.split(',').map(str::to_string).collect(),
+                key,
+                revoked,
+            });
+        }
+        Self { entries }
+    }
+
+    //load known_hosts from the file pointed to by KNOWN_HOSTS_PATH, if set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match std::env::var("KNOWN_HOSTS_PATH") {
+            Ok(path) => {
+                let contents = std::fs::read_to_string(&path).map_err(|e| {
+                    anyhow::anyhow!("Could not read known_hosts file {}: {}", path, e)
+                })?;
+                Ok(Some(Self::parse(&contents)))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    //check that the raw host key presented by host:port is pinned and not revoked
+    pub fn verify(&self, host: &str, port: u16, key: &[u8]) -> bool {
+        let pattern = if port == 22 {
+            host.to_string()
+        } else {
+            format!("[{host}]:{port}")
+        };
+        let mut matching = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry
+                    .patterns
+                    .iter()
+                    .any(|p| p.eq_ignore_ascii_case(&pattern))
+            })
+            .filter(|entry| entry.key == key)
+            .peekable();
+        matching.peek().is_some() && matching.all(|entry| !entry.revoked)
+    }
+}
+
+//port of an ssh remote, defaults to 22 for scp-like urls
+fn ssh_port(remote_url: &str) -> u16 {
+    remote_url
+        .strip_prefix("ssh://")
+        .and_then(|rest| rest.split('/').next())
+        .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+        .and_then(|host_port| host_port.rsplit_once(':'))
+        .and_then(|(_, port)| port.parse().ok())
+        .unwrap_or(22)
+}
+
+//verify ssh host keys against known_hosts, leave x509 certificates to libgit2
+fn certificate_check(
+    known_hosts: Option<&KnownHosts>,
+    port: u16,
+    cert: &Cert<'_>,
+    host: &str,
+) -> Result<CertificateCheckStatus, git2::Error> {
+    let (Some(known_hosts), Some(hostkey)) = (known_hosts, cert.as_hostkey()) else {
+        return Ok(CertificateCheckStatus::CertificatePassthrough);
+    };
+    let Some(key) = hostkey.hostkey() else {
+        return Err(git2::Error::new(
+            ErrorCode::Certificate,
+            ErrorClass::Ssh,
+            format!("Host {host} did not present a host key"),
+        ));
+    };
+    if known_hosts.verify(host, port, key) {
+        Ok(CertificateCheckStatus::CertificateOk)
+    } else {
+        log::error!("Host key for {} is not in known_hosts", host);
+        Err(git2::Error::new(
+            ErrorCode::Certificate,
+            ErrorClass::Ssh,
+            format!("Host key for {host} is not in known_hosts"),
+        ))
+    }
 }
 
 impl std::fmt::Debug for Repository {
@@ -23,40 +228,26 @@ impl Repository {
         remote_url: &str,
         remote_branch: &str,
         target_path: &str,
-        deploy_key: Option<&str>,
+        credentials: &GitCredentials,
+        known_hosts: Option<KnownHosts>,
     ) -> anyhow::Result<Self> {
         let mut callbacks = git2::RemoteCallbacks::new();
+        let port = ssh_port(remote_url);
+        let pinned_hosts = known_hosts.clone();
+        callbacks.certificate_check(move |cert, host| {
+            certificate_check(pinned_hosts.as_ref(), port, cert, host)
+        });
 
-        let deploy_key = deploy_key.unwrap_or("");
-
-        //if url is ssh, use ssh key
-        let cred_type = if remote_url.starts_with("https://") {
-            CredentialType::USER_PASS_PLAINTEXT
-        } else {
-            CredentialType::SSH_KEY
-        };
-
-        match cred_type {
-            CredentialType::SSH_KEY => {
-                callbacks.credentials(|_url, username_from_url, _allowed_types| {
-                    let user = username_from_url.unwrap_or("git");
-                    git2::Cred::ssh_key_from_memory(user, None, deploy_key, None)
-                });
-            }
-            CredentialType::USER_PASS_PLAINTEXT => {
-                callbacks.credentials(|_url, username_from_url, _allowed_types| {
-                    let user = username_from_url.unwrap_or("git");
-                    git2::Cred::userpass_plaintext(user, deploy_key)
-                });
-            }
-            _ => {
-                log::error!("Unknown credential type");
-                ::std::process::exit(1);
-            }
-        }
+        let git_credentials = credentials.clone();
+        callbacks.credentials(move |_url, username_from_url, _allowed_types| {
+            git_credentials.cred(username_from_url)
+        });
+        let headers = credentials.headers();
+        let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
 
         let mut fetch_options = git2::FetchOptions::new();
         fetch_options.remote_callbacks(callbacks);
+        fetch_options.custom_headers(&headers);
 
         let mut builder = git2::build::RepoBuilder::new();
         builder.fetch_options(fetch_options);
@@ -69,65 +260,433 @@ impl Repository {
         Ok(Self {
             inner: repo,
             base_path: PathBuf::from(target_path),
-            deploy_key: Some(deploy_key.to_string()),
-            cred_type,
+            credentials: credentials.clone(),
+            known_hosts,
+            ssh_port: port,
         })
     }
 
-    //commit repository
-    pub fn commit(&self, message: &str) -> anyhow::Result<()> {
+    //commit exactly the changed paths, returns None when the tree is unchanged
+    pub fn commit(&self, message: &str, changes: &Changes) -> anyhow::Result<Option<String>> {
         let mut index = self.inner.index()?;
-        index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
+        for path in changes.added.iter().chain(changes.modified.iter()) {
+            index.add_path(path)?;
+        }
+        for path in changes.deleted.iter() {
+            if index.get_path(path, 0).is_some() {
+                index.remove_path(path)?;
+            } else {
+                index.remove_dir(path, 0)?;
+            }
+        }
         let oid = index.write_tree()?;
         let tree = self.inner.find_tree(oid)?;
 
+        let parent_commit = self.inner.head()?.peel_to_commit()?;
+        if parent_commit.tree_id() == oid {
+            log::info!("Nothing to commit in {}", self.base_path.to_string_lossy());
+            return Ok(None);
+        }
+        index.write()?;
+
         let sig = git2::Signature::now("kyotu-project-operator", "no-reply@kyotutechnology.com")?;
+        let commit_id =
+            self.inner
+                .commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent_commit])?;
 
-        let parent_commit = self.inner.head()?.peel_to_commit()?;
-        self.inner
-            .commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent_commit])?;
+        Ok(Some(commit_id.to_string()))
+    }
+    //unified diff of the changed paths in the working tree against HEAD
+    pub fn diff(&self, changes: &Changes) -> anyhow::Result<String> {
+        let mut options = git2::DiffOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        for path in changes
+            .added
+            .iter()
+            .chain(changes.modified.iter())
+            .chain(changes.deleted.iter())
+        {
+            options.pathspec(path);
+        }
 
-        Ok(())
+        let head = self.inner.head()?.peel_to_tree()?;
+        let diff = self
+            .inner
+            .diff_tree_to_workdir(Some(&head), Some(&mut options))?;
+        let mut patch = String::new();
+        diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+            if let origin @ ('+' | '-' | ' ') = line.origin() {
+                patch.push(origin);
+            }
+            patch.push_str(&String::from_utf8_lossy(line.content()));
+            true
+        })?;
+        Ok(patch)
     }
+
     //push repository
     pub fn push(&self, target_branch: &str) -> anyhow::Result<()> {
         let mut remote = self.inner.find_remote("origin")?;
         let mut push_options = git2::PushOptions::new();
         let mut push_callbacks = git2::RemoteCallbacks::new();
+        push_callbacks.certificate_check(|cert, host| {
+            certificate_check(self.known_hosts.as_ref(), self.ssh_port, cert, host)
+        });
 
-        match self.cred_type {
-            CredentialType::SSH_KEY => {
-                push_callbacks.credentials(|_url, username_from_url, _allowed_types| {
-                    let user = username_from_url.unwrap_or("git");
-                    git2::Cred::ssh_key_from_memory(
-                        user,
-                        None,
-                        self.deploy_key.as_ref().unwrap(),
-                        None,
-                    )
-                });
-            }
-            CredentialType::USER_PASS_PLAINTEXT => {
-                push_callbacks.credentials(|_url, username_from_url, _allowed_types| {
-                    let user = username_from_url.unwrap_or("git");
-                    git2::Cred::userpass_plaintext(user, self.deploy_key.as_ref().unwrap())
+        push_callbacks.credentials(|_url, username_from_url, _allowed_types| {
+            self.credentials.cred(username_from_url)
+        });
+        let headers = self.credentials.headers();
+        let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+
+        push_options.remote_callbacks(push_callbacks);
+        push_options.custom_headers(&headers);
+
+        remote.push(
+            &[&format!("refs/heads/{target_branch}")],
+            Some(&mut push_options),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::ssh_key::LineEnding;
+    use russh::keys::{Algorithm, PrivateKey};
+    use russh::server::{Auth, Handler, Msg, Session};
+    use russh::{Channel, ChannelId};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::process::Command;
+
+    //minimal ssh server that serves git-upload-pack from the local filesystem
+    struct GitSshStandIn {
+        channels: HashMap<ChannelId, Channel<Msg>>,
+    }
+
+    impl Handler for GitSshStandIn {
+        type Error = russh::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _user: &str,
+            _key: &russh::keys::PublicKey,
+        ) -> Result<Auth, Self::Error> {
+            Ok(Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<Msg>,
+            _session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            self.channels.insert(channel.id(), channel);
+            Ok(true)
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel_id: ChannelId,
+            data: &[u8],
+            session: &mut Session,
+        ) -> Result<(), Self::Error> {
+            let command = String::from_utf8_lossy(data).to_string();
+            let (service, path) = command.split_once(' ').unwrap();
+            let service = service.trim_start_matches("git-").to_string();
+            let path = path.trim_matches('\'').to_string();
+            let channel = self.channels.remove(&channel_id).unwrap();
+            session.channel_success(channel_id)?;
+
+            tokio::spawn(async move {
+                let (mut read_half, write_half) = channel.split();
+                let mut child = Command::new("git")
+                    .arg(service)
+                    .arg(path)
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+                    .unwrap();
+                let mut stdin = child.stdin.take().unwrap();
+                let stdout = child.stdout.take().unwrap();
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy(&mut read_half.make_reader(), &mut stdin).await;
                 });
-            }
-            _ => {
-                log::error!("Unknown credential type");
-                ::std::process::exit(1);
-            }
+                let _ = write_half.data(stdout).await;
+                let status = child.wait().await.unwrap();
+                let _ = write_half
+                    .exit_status(status.code().unwrap_or(1) as u32)
+                    .await;
+                let _ = write_half.eof().await;
+                let _ = write_half.close().await;
+            });
+            Ok(())
         }
+    }
 
-        push_options.remote_callbacks(push_callbacks);
+    //start the stand-in on a random local port, returns the port and the host key line
+    async fn start_stand_in() -> (u16, String) {
+        let host_key = PrivateKey::random(&mut rand::rngs::OsRng, Algorithm::Ed25519).unwrap();
+        let public_key = host_key.public_key().to_openssh().unwrap();
+        let config = Arc::new(russh::server::Config {
+            keys: vec![host_key],
+            auth_rejection_time: std::time::Duration::from_millis(10),
+            ..Default::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let handler = GitSshStandIn {
+                    channels: HashMap::new(),
+                };
+                let _ = russh::server::run_stream(config.clone(), stream, handler).await;
+            }
+        });
+        (port, public_key)
+    }
+
+    //create a source repository with a single commit on main
+    fn init_source_repo(path: &Path) {
+        let repo = git2::Repository::init(path).unwrap();
+        std::fs::write(path.join("README.md"), "test").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("README.md")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+            .unwrap();
+    }
 
-        remote
-            .push(
-                &[&format!("refs/heads/{target_branch}")],
-                Some(&mut push_options),
+    fn client_credentials() -> GitCredentials {
+        let private_key = PrivateKey::random(&mut rand::rngs::OsRng, Algorithm::Ed25519)
+            .unwrap()
+            .to_openssh(LineEnding::LF)
+            .unwrap()
+            .to_string();
+        GitCredentials::SshKey {
+            private_key,
+            passphrase: None,
+        }
+    }
+
+    async fn clone_with(
+        port: u16,
+        known_hosts: KnownHosts,
+        target: &Path,
+    ) -> anyhow::Result<Repository> {
+        let source = tempfile::tempdir().unwrap();
+        init_source_repo(source.path());
+        let url = format!("ssh://git@127.0.0.1:{port}{}", source.path().display());
+        let target_path = target.join("clone");
+        tokio::task::spawn_blocking(move || {
+            let _source = source;
+            Repository::clone(
+                &url,
+                "main",
+                &target_path.to_string_lossy(),
+                &client_credentials(),
+                Some(known_hosts),
             )
-            .expect("Could not push to remote");
+        })
+        .await
+        .unwrap()
+    }
 
-        Ok(())
+    #[test]
+    fn test_known_hosts_verify() {
+        let key = BASE64.encode(b"key-one");
+        let other = BASE64.encode(b"key-two");
+        let known_hosts = KnownHosts::parse(&format!(
+            "# comment\ngithub.com,gitlab.com ssh-ed25519 {key}\n[localhost]:2222 ssh-ed25519 {other}\n|1|salt|hash ssh-ed25519 {key}\n"
+        ));
+
+        assert!(known_hosts.verify("github.com", 22, b"key-one"));
+        assert!(known_hosts.verify("gitlab.com", 22, b"key-one"));
+        assert!(!known_hosts.verify("github.com", 22, b"key-two"));
+        assert!(!known_hosts.verify("github.com", 2222, b"key-one"));
+        assert!(known_hosts.verify("localhost", 2222, b"key-two"));
+        assert!(!known_hosts.verify("localhost", 22, b"key-two"));
+        assert!(!known_hosts.verify("example.com", 22, b"key-one"));
+    }
+
+    #[test]
+    fn test_known_hosts_revoked() {
+        let key = BASE64.encode(b"key-one");
+        let known_hosts = KnownHosts::parse(&format!(
+            "github.com ssh-ed25519 {key}\n@revoked github.com ssh-ed25519 {key}\n"
+        ));
+        assert!(!known_hosts.verify("github.com", 22, b"key-one"));
+    }
+
+    #[test]
+    fn test_ssh_port() {
+        assert_eq!(ssh_port("git@github.com:Kyotu-Technology/flux.git"), 22);
+        assert_eq!(ssh_port("ssh://git@127.0.0.1:2222/repo.git"), 2222);
+        assert_eq!(ssh_port("ssh://127.0.0.1/repo.git"), 22);
+        assert_eq!(ssh_port("https://gitlab.com/repo.git"), 22);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_clone_with_pinned_host_key() {
+        let (port, public_key) = start_stand_in().await;
+        let known_hosts = KnownHosts::parse(&format!("[127.0.0.1]:{port} {public_key}"));
+
+        let target = tempfile::tempdir().unwrap();
+
+        let repo = clone_with(port, known_hosts, target.path()).await.unwrap();
+        assert!(repo.base_path.join("README.md").exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_clone_rejects_unknown_host_key() {
+        let (port, public_key) = start_stand_in().await;
+        let known_hosts = KnownHosts::parse(&format!("github.com {public_key}"));
+
+        let target = tempfile::tempdir().unwrap();
+
+        let err = clone_with(port, known_hosts, target.path())
+            .await
+            .unwrap_err();
+        let err = err.downcast::<git2::Error>().unwrap();
+        assert_eq!(err.code(), ErrorCode::Certificate);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_clone_rejects_mismatched_host_key() {
+        let (port, _) = start_stand_in().await;
+        let (_, other_key) = start_stand_in().await;
+        let known_hosts = KnownHosts::parse(&format!("[127.0.0.1]:{port} {other_key}"));
+
+        let target = tempfile::tempdir().unwrap();
+
+        let err = clone_with(port, known_hosts, target.path())
+            .await
+            .unwrap_err();
+        let err = err.downcast::<git2::Error>().unwrap();
+        assert_eq!(err.code(), ErrorCode::Certificate);
+    }
+
+    fn clone_local(source: &Path, target: &Path) -> Repository {
+        Repository::clone(
+            &source.to_string_lossy(),
+            "main",
+            &target.join("clone").to_string_lossy(),
+            &client_credentials(),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn head_paths(repo: &Repository) -> Vec<String> {
+        let tree = repo.inner.head().unwrap().peel_to_tree().unwrap();
+        let mut paths = vec![];
+        tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                paths.push(format!("{root}{}", entry.name().unwrap()));
+            }
+            git2::TreeWalkResult::Ok
+        })
+        .unwrap();
+        paths
+    }
+
+    #[test]
+    fn test_commit_stages_only_changed_paths() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        init_source_repo(source.path());
+        let repo = clone_local(source.path(), target.path());
+
+        std::fs::create_dir_all(repo.base_path.join("manifests/test")).unwrap();
+        std::fs::write(repo.base_path.join("manifests/test/.gitkeep"), "").unwrap();
+        std::fs::write(repo.base_path.join("stray.txt"), "leftover").unwrap();
+        std::fs::remove_file(repo.base_path.join("README.md")).unwrap();
+        let changes = Changes {
+            added: vec![PathBuf::from("manifests/test/.gitkeep")],
+            deleted: vec![PathBuf::from("README.md")],
+            ..Default::default()
+        };
+
+        let commit = repo.commit("test", &changes).unwrap().unwrap();
+        assert_eq!(
+            repo.inner.head().unwrap().target().unwrap().to_string(),
+            commit
+        );
+        assert_eq!(head_paths(&repo), vec!["manifests/test/.gitkeep"]);
+    }
+
+    #[test]
+    fn test_commit_deletes_directories() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        init_source_repo(source.path());
+        let repo = clone_local(source.path(), target.path());
+
+        std::fs::create_dir_all(repo.base_path.join("manifests/test")).unwrap();
+        std::fs::write(repo.base_path.join("manifests/test/.gitkeep"), "").unwrap();
+        let mut changes = Changes::default();
+        changes.write(&repo.base_path, PathBuf::from("manifests/test/.gitkeep"));
+        repo.commit("add", &changes).unwrap().unwrap();
+
+        std::fs::remove_dir_all(repo.base_path.join("manifests/test")).unwrap();
+        let mut changes = Changes::default();
+        changes.delete(PathBuf::from("manifests/test"));
+        repo.commit("delete", &changes).unwrap().unwrap();
+        assert_eq!(head_paths(&repo), vec!["README.md"]);
+    }
+
+    #[test]
+    fn test_commit_skips_unchanged_tree() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        init_source_repo(source.path());
+        let repo = clone_local(source.path(), target.path());
+        let head = repo.inner.head().unwrap().target().unwrap();
+
+        let mut changes = Changes::default();
+        changes.write(&repo.base_path, PathBuf::from("README.md"));
+        assert_eq!(changes.modified, vec![PathBuf::from("README.md")]);
+
+        assert_eq!(repo.commit("noop", &changes).unwrap(), None);
+        assert_eq!(repo.inner.head().unwrap().target().unwrap(), head);
+    }
+
+    #[test]
+    fn test_diff_only_changed_paths() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        init_source_repo(source.path());
+        let repo = clone_local(source.path(), target.path());
+        let head = repo.inner.head().unwrap().target().unwrap();
+
+        std::fs::create_dir_all(repo.base_path.join("applications")).unwrap();
+        std::fs::write(
+            repo.base_path.join("applications/test.yaml"),
+            "kind: AppProject\n",
+        )
+        .unwrap();
+        std::fs::write(repo.base_path.join("stray.txt"), "leftover").unwrap();
+        std::fs::remove_file(repo.base_path.join("README.md")).unwrap();
+        let changes = Changes {
+            added: vec![PathBuf::from("applications/test.yaml")],
+            deleted: vec![PathBuf::from("README.md")],
+            ..Default::default()
+        };
+
+        let diff = repo.diff(&changes).unwrap();
+        assert!(diff.contains("+++ b/applications/test.yaml"));
+        assert!(diff.contains("+kind: AppProject"));
+        assert!(diff.contains("--- a/README.md"));
+        assert!(diff.contains("-test"));
+        assert!(!diff.contains("stray.txt"));
+        assert_eq!(repo.inner.head().unwrap().target().unwrap(), head);
     }
 }
@@ -0,0 +1,155 @@
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// When Projects are reconciled again, after a success or an error
+#[derive(Clone)]
+pub struct Requeue {
+    /// Interval between resyncs of a reconciled Project
+    pub resync_interval: Duration,
+    /// Share of the resync interval added at random, so Projects are not resynced together
+    pub jitter: f64,
+    /// Delay after the first error of a Project, doubled on every error in a row
+    pub backoff_base: Duration,
+    /// Longest delay after an error
+    pub backoff_max: Duration,
+    /// Backoff of the Projects failing, keyed by `<namespace>/<name>`
+    failures: Arc<Mutex<BTreeMap<String, Failures>>>,
+}
+
+struct Failures {
+    count: u32,
+    backoff: ExponentialBackoff,
+}
+
+impl Requeue {
+    pub fn new(
+        resync_interval: Duration,
+        jitter: f64,
+        backoff_base: Duration,
+        backoff_max: Duration,
+    ) -> Self {
+        Self {
+            resync_interval,
+            jitter,
+            backoff_base,
+            backoff_max,
+            failures: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    //RESYNC_INTERVAL, RESYNC_JITTER, ERROR_BACKOFF_BASE and ERROR_BACKOFF_MAX, durations in seconds
+    pub fn from_env() -> Self {
+        let seconds = |name: &str, default: u64| {
+            Duration::from_secs(
+                std::env::var(name)
+                    .ok()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+        let jitter = std::env::var("RESYNC_JITTER")
+            .ok()
+            .and_then(|jitter| jitter.parse().ok())
+            .unwrap_or(0.1);
+        Self::new(
+            seconds("RESYNC_INTERVAL", 600),
+            jitter,
+            seconds("ERROR_BACKOFF_BASE", 5),
+            seconds("ERROR_BACKOFF_MAX", 300),
+        )
+    }
+
+    //resync interval with up to `jitter` of it added
+    pub fn resync(&self) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return self.resync_interval;
+        }
+        self.resync_interval
+            .mul_f64(1.0 + rand::thread_rng().gen_range(0.0..jitter))
+    }
+
+    //record an error of a project, returning the errors in a row and the delay before the retry
+    pub fn failed(&self, key: &str) -> (u32, Duration) {
+        let mut failures = self.failures.lock().unwrap();
+        let failures = failures.entry(key.to_string()).or_insert_with(|| Failures {
+            count: 0,
+            backoff: ExponentialBackoff {
+                current_interval: self.backoff_base,
+                initial_interval: self.backoff_base,
+                randomization_factor: 0.0,
+                multiplier: 2.0,
+                max_interval: self.backoff_max,
+                max_elapsed_time: None,
+                ..Default::default()
+            },
+        });
+        failures.count += 1;
+        let delay = failures
+            .backoff
+            .next_backoff()
+            .unwrap_or(self.backoff_max)
+            .min(self.backoff_max);
+        (failures.count, delay)
+    }
+
+    //forget the errors of a project, returning whether it was failing
+    pub fn succeeded(&self, key: &str) -> bool {
+        self.failures.lock().unwrap().remove(key).is_some()
+    }
+
+    //drop a deleted project, so its errors are not kept until the operator restarts
+    pub fn forget(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requeue() -> Requeue {
+        Requeue::new(
+            Duration::from_secs(600),
+            0.1,
+            Duration::from_secs(5),
+            Duration::from_secs(30),
+        )
+    }
+
+    #[test]
+    fn test_resync_jitter() {
+        let requeue = requeue();
+        for _ in 0..20 {
+            let resync = requeue.resync();
+            assert!(resync >= Duration::from_secs(600));
+            assert!(resync < Duration::from_secs(660));
+        }
+        let fixed = Requeue {
+            jitter: 0.0,
+            ..requeue
+        };
+        assert_eq!(fixed.resync(), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_backoff_is_capped_and_reset() {
+        let requeue = requeue();
+        let delays = (0..5)
+            .map(|_| requeue.failed("default/demo").1.as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![5, 10, 20, 30, 30]);
+        assert_eq!(requeue.failed("default/other"), (1, Duration::from_secs(5)));
+
+        assert!(requeue.succeeded("default/demo"));
+        assert!(!requeue.succeeded("default/demo"));
+        assert_eq!(requeue.failed("default/demo"), (1, Duration::from_secs(5)));
+
+        requeue.forget("default/other");
+        assert!(!requeue.succeeded("default/other"));
+    }
+}
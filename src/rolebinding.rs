@@ -0,0 +1,177 @@
+use k8s_openapi::api::rbac::v1::{RoleBinding, RoleRef, Subject};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams, PostParams};
+use kube::{Api, Client};
+use std::collections::BTreeMap;
+
+use crate::owner::ProjectOwner;
+
+/// Name of the RoleBinding granting the project group access to its namespace
+pub const ROLEBINDING_NAME: &str = "kyotu-project-group";
+
+//cluster role bound for an environment, GROUP_CLUSTER_ROLE_<ENVIRONMENT> overrides view for prod and edit otherwise
+pub fn cluster_role(environment_type: &str) -> String {
+    std::env::var(format!(
+        "GROUP_CLUSTER_ROLE_{}",
+        environment_type.to_uppercase()
+    ))
+    .unwrap_or_else(|_| match environment_type {
+        "prod" => "view".to_string(),
+        _ => std::env::var("GROUP_CLUSTER_ROLE").unwrap_or("edit".to_string()),
+    })
+}
+
+//rolebinding of the google group, named as in the OIDC group claim with OIDC_GROUP_PREFIX
+pub fn group_rolebinding(namespace: &str, google_group: &str, cluster_role: &str) -> RoleBinding {
+    let mut labels: BTreeMap<String, String> = BTreeMap::new();
+    labels.insert("app".to_string(), "kyotu-project-operator".to_string());
+    let prefix = std::env::var("OIDC_GROUP_PREFIX").unwrap_or_default();
+
+    RoleBinding {
+        metadata: ObjectMeta {
+            name: Some(ROLEBINDING_NAME.to_string()),
+            namespace: Some(namespace.to_string()),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "ClusterRole".to_string(),
+            name: cluster_role.to_string(),
+        },
+        subjects: Some(vec![Subject {
+            api_group: Some("rbac.authorization.k8s.io".to_string()),
+            kind: "Group".to_string(),
+            name: format!("{prefix}{google_group}"),
+            namespace: None,
+        }]),
+    }
+}
+
+//create rolebinding, replacing one created by the operator when the group or cluster role changed
+pub async fn create_rolebinding(
+    client: Client,
+    namespace: &str,
+    google_group: &str,
+    environment_type: &str,
+    owner: &ProjectOwner,
+) -> anyhow::Result<String> {
+    let mut rolebinding =
+        group_rolebinding(namespace, google_group, &cluster_role(environment_type));
+    owner.own(&mut rolebinding.metadata);
+    let rolebinding_api: Api<RoleBinding> = Api::namespaced(client, namespace);
+
+    //check if rolebinding exists
+    match rolebinding_api.get_opt(ROLEBINDING_NAME).await? {
+        None => {
+            let res = rolebinding_api
+                .create(&PostParams::default(), &rolebinding)
+                .await?;
+            log::info!(
+                "Created rolebinding {} in namespace {}",
+                res.metadata.name.unwrap(),
+                namespace
+            );
+        }
+        Some(existing) if !is_managed(&existing) => {
+            log::warn!(
+                "RoleBinding {} in namespace {} does not have label app=kyotu-project-operator",
+                ROLEBINDING_NAME,
+                namespace
+            );
+        }
+        Some(existing)
+            if existing.role_ref == rolebinding.role_ref
+                && existing.subjects == rolebinding.subjects =>
+        {
+            if !owner.labelled(&existing.metadata) {
+                rolebinding_api
+                    .patch(
+                        ROLEBINDING_NAME,
+                        &PatchParams::default(),
+                        &Patch::Merge(&owner.metadata_patch(Some(namespace))),
+                    )
+                    .await?;
+            }
+        }
+        Some(existing) => {
+            //roleRef is immutable, so the binding is recreated
+            rolebinding_api
+                .delete(ROLEBINDING_NAME, &DeleteParams::default())
+                .await?;
+            rolebinding_api
+                .create(&PostParams::default(), &rolebinding)
+                .await?;
+            log::info!(
+                "Replaced rolebinding {} in namespace {}, was bound to {}",
+                ROLEBINDING_NAME,
+                namespace,
+                existing.role_ref.name
+            );
+        }
+    }
+    Ok(namespace.to_string())
+}
+
+//delete rolebinding
+pub async fn delete_rolebinding(client: Client, namespace: &str) -> anyhow::Result<String> {
+    let rolebinding_api: Api<RoleBinding> = Api::namespaced(client, namespace);
+    //delete only if label app=kyotu-project-operator is present
+    match rolebinding_api.get_opt(ROLEBINDING_NAME).await? {
+        Some(existing) if is_managed(&existing) => {
+            rolebinding_api
+                .delete(ROLEBINDING_NAME, &DeleteParams::default())
+                .await?;
+            log::info!("Deleted rolebinding in namespace {}", namespace);
+        }
+        Some(_) => {
+            log::warn!(
+                "RoleBinding {} in namespace {} does not have label app=kyotu-project-operator",
+                ROLEBINDING_NAME,
+                namespace
+            );
+        }
+        None => {
+            log::warn!(
+                "RoleBinding {} does not exist in namespace {}",
+                ROLEBINDING_NAME,
+                namespace
+            );
+        }
+    }
+    Ok(namespace.to_string())
+}
+
+fn is_managed(rolebinding: &RoleBinding) -> bool {
+    rolebinding
+        .metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get("app"))
+        .is_some_and(|app| app == "kyotu-project-operator")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_role_per_environment() {
+        assert_eq!(cluster_role("prod"), "view");
+        assert_eq!(cluster_role("dev"), "edit");
+    }
+
+    #[test]
+    fn test_group_rolebinding() {
+        let rolebinding = group_rolebinding("demo-dev", "demo@kyotu.tech", "edit");
+        assert_eq!(rolebinding.role_ref.name, "edit");
+        assert_eq!(rolebinding.role_ref.kind, "ClusterRole");
+        let subjects = rolebinding.subjects.unwrap();
+        assert_eq!(subjects[0].kind, "Group");
+        assert_eq!(subjects[0].name, "demo@kyotu.tech");
+        assert!(is_managed(&group_rolebinding(
+            "demo-dev",
+            "demo@kyotu.tech",
+            "view"
+        )));
+    }
+}
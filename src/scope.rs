@@ -0,0 +1,200 @@
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use kube::runtime::reflector::{ObjectRef, Store};
+use kube::runtime::watcher::{self, watcher, Config, Event};
+use kube::{Api, Client, ResourceExt};
+use std::sync::Arc;
+
+use crate::owner::{PROJECT_NAMESPACE_LABEL, PROJECT_UID_LABEL};
+use crate::project_crd::Project;
+
+/// Projects reconciled by this operator instance
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scope {
+    /// Namespaces watched for Projects, every namespace when empty
+    pub namespaces: Vec<String>,
+    /// Label selector Projects must match, such as `kyotu.tech/operator-instance=bu1`
+    pub selector: Option<String>,
+}
+
+impl Scope {
+    //scope configured with WATCH_NAMESPACES, comma separated, and PROJECT_SELECTOR
+    pub fn from_env() -> Self {
+        Self::new(
+            &std::env::var("WATCH_NAMESPACES").unwrap_or_default(),
+            &std::env::var("PROJECT_SELECTOR").unwrap_or_default(),
+        )
+    }
+
+    pub fn new(namespaces: &str, selector: &str) -> Self {
+        Self {
+            namespaces: namespaces
+                .split(',')
+                .map(str::trim)
+                .filter(|namespace| !namespace.is_empty())
+                .map(str::to_string)
+                .collect(),
+            selector: Some(selector.trim())
+                .filter(|selector| !selector.is_empty())
+                .map(str::to_string),
+        }
+    }
+
+    //namespaces to run a controller for, a single cluster wide one when none are set
+    pub fn watched(&self) -> Vec<Option<String>> {
+        if self.namespaces.is_empty() {
+            vec![None]
+        } else {
+            self.namespaces.iter().cloned().map(Some).collect()
+        }
+    }
+
+    //watcher config of Projects, limited to the selector
+    pub fn watcher_config(&self) -> Config {
+        let config = Config::default().any_semantic();
+        match &self.selector {
+            Some(selector) => config.labels(selector),
+            None => config,
+        }
+    }
+
+    //watcher config of objects created for Projects in the watched namespaces
+    pub fn owned_config(&self) -> Config {
+        if self.namespaces.is_empty() {
+            return Config::default().labels(PROJECT_UID_LABEL);
+        }
+        Config::default().labels(&format!(
+            "{PROJECT_UID_LABEL},{PROJECT_NAMESPACE_LABEL} in ({})",
+            self.namespaces.join(",")
+        ))
+    }
+
+    //Project events of every watched namespace merged into one stream for a shared store
+    pub fn project_events(
+        &self,
+        client: Client,
+        store: Store<Project>,
+    ) -> impl Stream<Item = watcher::Result<Event<Project>>> + Send + 'static {
+        let watches = self.watched().into_iter().map(|namespace| {
+            let projects: Api<Project> = match &namespace {
+                Some(namespace) => Api::namespaced(client.clone(), namespace),
+                None => Api::all(client.clone()),
+            };
+            let store = store.clone();
+            watcher(projects, self.watcher_config())
+                .map_ok(move |event| {
+                    let events = match event {
+                        Event::Restarted(projects) => {
+                            restarted(namespace.as_deref(), projects, &store.state())
+                        }
+                        event => vec![event],
+                    };
+                    stream::iter(events.into_iter().map(Ok))
+                })
+                .try_flatten()
+                .boxed()
+        });
+        stream::select_all(watches)
+    }
+}
+
+//a restart replaces the whole store, so the restart of one namespace is applied as
+//deletions of its Projects that are gone and updates of the listed ones
+fn restarted(
+    namespace: Option<&str>,
+    projects: Vec<Project>,
+    current: &[Arc<Project>],
+) -> Vec<Event<Project>> {
+    let listed = projects.iter().map(ObjectRef::from_obj).collect::<Vec<_>>();
+    let mut events = current
+        .iter()
+        .filter(|project| namespace.is_none() || project.namespace().as_deref() == namespace)
+        .filter(|project| !listed.contains(&ObjectRef::from_obj(project.as_ref())))
+        .map(|project| Event::Deleted(project.as_ref().clone()))
+        .collect::<Vec<_>>();
+    events.extend(projects.into_iter().map(Event::Applied));
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_wide_by_default() {
+        let scope = Scope::new("", " ");
+        assert_eq!(scope, Scope::default());
+        assert_eq!(scope.watched(), vec![None]);
+        assert!(scope.watcher_config().label_selector.is_none());
+        assert_eq!(
+            scope.owned_config().label_selector.as_deref(),
+            Some(PROJECT_UID_LABEL)
+        );
+    }
+
+    fn project(namespace: &str, name: &str) -> Project {
+        let mut project = Project::new(
+            name,
+            crate::project_crd::ProjectSpec {
+                project_id: name.to_string(),
+                environment_type: "dev".to_string(),
+                google_group: "demo@kyotu.tech".to_string(),
+                vault: None,
+                quota: None,
+                network: None,
+                namespace: None,
+                adopt_existing_namespace: false,
+            },
+        );
+        project.metadata.namespace = Some(namespace.to_string());
+        project
+    }
+
+    #[test]
+    fn test_restart_only_replaces_its_namespace() {
+        let current = [
+            project("bu1-projects", "kept"),
+            project("bu1-projects", "deleted"),
+            project("bu1-sandbox", "other"),
+        ]
+        .map(Arc::new);
+        let events = restarted(
+            Some("bu1-projects"),
+            vec![project("bu1-projects", "kept")],
+            &current,
+        );
+        let names = events
+            .iter()
+            .map(|event| match event {
+                Event::Deleted(project) => format!("deleted {}", project.name_any()),
+                Event::Applied(project) => format!("applied {}", project.name_any()),
+                Event::Restarted(_) => "restarted".to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["deleted deleted", "applied kept"]);
+    }
+
+    #[test]
+    fn test_namespaces_and_selector() {
+        let scope = Scope::new(
+            "bu1-projects, bu1-sandbox,",
+            "kyotu.tech/operator-instance=bu1",
+        );
+        assert_eq!(
+            scope.watched(),
+            vec![
+                Some("bu1-projects".to_string()),
+                Some("bu1-sandbox".to_string())
+            ]
+        );
+        assert_eq!(
+            scope.watcher_config().label_selector.as_deref(),
+            Some("kyotu.tech/operator-instance=bu1")
+        );
+        assert_eq!(
+            scope.owned_config().label_selector.as_deref(),
+            Some(
+                "kyotu.tech/project-uid,kyotu.tech/project-namespace in (bu1-projects,bu1-sandbox)"
+            )
+        );
+    }
+}
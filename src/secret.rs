@@ -2,13 +2,24 @@ use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::engine::Engine as _;
 use k8s_openapi::api::core::v1::Secret;
 use k8s_openapi::ByteString;
-use kube::api::{DeleteParams, ObjectMeta, PostParams};
+use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams, PostParams};
 use kube::{Api, Client};
 use serde_json::json;
 use std::collections::BTreeMap;
+
+use crate::owner::ProjectOwner;
+
+/// Name of the registry pull secret created in the project namespace
+pub const PULL_SECRET_NAME: &str = "gitlab-registry-image-pull-secret";
+
 //create secret
 
-pub async fn create_secret(client: Client, namespace: &str, data: &str) -> anyhow::Result<String> {
+pub async fn create_secret(
+    client: Client,
+    namespace: &str,
+    data: &str,
+    owner: &ProjectOwner,
+) -> anyhow::Result<String> {
     let mut labels: BTreeMap<String, String> = BTreeMap::new();
     labels.insert("app".to_string(), "kyotu-project-operator".to_string());
     let mut data_map: BTreeMap<String, ByteString> = BTreeMap::new();
@@ -38,9 +49,9 @@ pub async fn create_secret(client: Client, namespace: &str, data: &str) -> anyho
         ),
     );
 
-    let secret = Secret {
+    let mut secret = Secret {
         metadata: ObjectMeta {
-            name: Some("gitlab-registry-image-pull-secret".to_string()),
+            name: Some(PULL_SECRET_NAME.to_string()),
             namespace: Some(namespace.to_string()),
             labels: Some(labels),
             ..Default::default()
@@ -49,16 +60,29 @@ pub async fn create_secret(client: Client, namespace: &str, data: &str) -> anyho
         type_: Some("kubernetes.io/dockerconfigjson".to_string()),
         ..Default::default()
     };
+    owner.own(&mut secret.metadata);
     let secret_api: Api<Secret> = Api::namespaced(client, namespace);
 
     //check if secret exists
-    let res = secret_api.get("gitlab-registry-image-pull-secret").await;
+    let res = secret_api.get(PULL_SECRET_NAME).await;
     match res {
-        Ok(_) => {
+        Ok(existing) => {
             log::warn!(
                 "Secret gitlab-registry-image-pull-secret already exists in namespace {}",
                 namespace
             );
+            //a managed secret gets the current token, and the project labels when created before them
+            if is_managed(&existing) {
+                let mut patch = owner.metadata_patch(Some(namespace));
+                patch["data"] = json!(secret.data);
+                secret_api
+                    .patch(
+                        PULL_SECRET_NAME,
+                        &PatchParams::default(),
+                        &Patch::Merge(&patch),
+                    )
+                    .await?;
+            }
             Ok(namespace.to_string())
         }
         Err(_) => {
@@ -73,7 +97,7 @@ pub async fn create_secret(client: Client, namespace: &str, data: &str) -> anyho
 pub async fn delete_secret(client: Client, namespace: &str) -> anyhow::Result<String> {
     let secret_api: Api<Secret> = Api::namespaced(client.clone(), namespace);
     //delete only if label app=kyotu-project-operator is present
-    let res = secret_api.get("gitlab-registry-image-pull-secret").await;
+    let res = secret_api.get(PULL_SECRET_NAME).await;
 
     match res {
         Ok(_) => {
@@ -86,9 +110,7 @@ pub async fn delete_secret(client: Client, namespace: &str) -> anyhow::Result<St
                 Ok(namespace.to_string())
             } else {
                 let dp = DeleteParams::default();
-                let _res = secret_api
-                    .delete("gitlab-registry-image-pull-secret", &dp)
-                    .await?;
+                let _res = secret_api.delete(PULL_SECRET_NAME, &dp).await?;
                 log::info!("Deleted secret {}", namespace.to_string());
                 Ok(namespace.to_string())
             }
@@ -102,3 +124,12 @@ pub async fn delete_secret(client: Client, namespace: &str) -> anyhow::Result<St
         }
     }
 }
+
+fn is_managed(secret: &Secret) -> bool {
+    secret
+        .metadata
+        .labels
+        .as_ref()
+        .and_then(|labels| labels.get("app"))
+        .is_some_and(|app| app == "kyotu-project-operator")
+}
@@ -0,0 +1,60 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Shutdown requested by a signal, shared by the controller and the web server
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Shutdown {
+    //stop starting git transactions and reconciles, the running ones finish
+    pub fn trigger(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    //future resolving once shutdown is triggered, also when it already was
+    pub fn triggered(&self) -> impl Future<Output = ()> + Send + Sync + 'static {
+        let requested = self.requested.clone();
+        let notify = self.notify.clone();
+        async move {
+            let notified = notify.notified();
+            if requested.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_triggered() {
+        let shutdown = Shutdown::default();
+        let waiting = tokio::spawn(shutdown.triggered());
+        tokio::task::yield_now().await;
+        assert!(!shutdown.requested());
+        shutdown.trigger();
+        assert!(shutdown.requested());
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        //triggering before waiting resolves right away
+        tokio::time::timeout(Duration::from_secs(1), shutdown.triggered())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,19 @@
+use crate::project_crd::{Project, ProjectStatus};
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client, Error};
+use serde_json::{json, Value};
+
+//patch project status
+pub async fn patch(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    status: &ProjectStatus,
+) -> Result<Project, Error> {
+    let api: Api<Project> = Api::namespaced(client, namespace);
+    let status: Value = json!({ "status": status });
+
+    let patch: Patch<&Value> = Patch::Merge(&status);
+    api.patch_status(name, &PatchParams::default(), &patch)
+        .await
+}
@@ -0,0 +1,194 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tera::{Context, Tera};
+use tokio::time::Duration;
+
+/// Tera templates parsed from a directory and shared by all reconciliations
+///
+/// The directory is polled for changes, so templates mounted from a ConfigMap are picked up
+/// without a restart. A template set that fails to parse is reported and the previous one is kept.
+#[derive(Clone)]
+pub struct Templates {
+    dir: PathBuf,
+    tera: Arc<RwLock<Tera>>,
+    fingerprint: Arc<Mutex<u64>>,
+}
+
+impl Templates {
+    //load templates from TEMPLATES_DIR, defaults to templates in the working directory
+    pub fn from_env() -> Self {
+        let dir = std::env::var("TEMPLATES_DIR").unwrap_or("templates".to_string());
+        Self::load(dir)
+    }
+
+    //parse templates, starting with an empty set when they do not parse
+    pub fn load(dir: impl Into<PathBuf>) -> Self {
+        let templates = Self {
+            dir: dir.into(),
+            tera: Arc::new(RwLock::new(Tera::default())),
+            fingerprint: Arc::new(Mutex::new(0)),
+        };
+        if let Err(e) = templates.reload() {
+            log::error!("{}", e);
+        }
+        templates
+    }
+
+    //render a template by file name
+    pub fn render(&self, name: &str, context: &Context) -> Result<String, TemplateError> {
+        self.tera
+            .read()
+            .unwrap()
+            .render(name, context)
+            .map_err(|e| TemplateError::RenderError(name.to_string(), describe(&e)))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tera
+            .read()
+            .unwrap()
+            .get_template_names()
+            .any(|template| template == name)
+    }
+
+    //reparse templates if the directory changed, returns whether they were replaced
+    pub fn reload(&self) -> Result<bool, TemplateError> {
+        let fingerprint = fingerprint(&self.dir)?;
+        let mut current = self.fingerprint.lock().unwrap();
+        if *current == fingerprint {
+            return Ok(false);
+        }
+        //remember the failed set too, so a broken template is reported once
+        *current = fingerprint;
+        let glob = format!("{}/*.{{yaml,hcl}}", self.dir.to_string_lossy());
+        let tera = Tera::new(&glob).map_err(|e| {
+            TemplateError::ParseError(self.dir.to_string_lossy().to_string(), describe(&e))
+        })?;
+        *self.tera.write().unwrap() = tera;
+        Ok(true)
+    }
+
+    //poll the template directory and reload on change
+    pub async fn watch(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match self.reload() {
+                Ok(true) => {
+                    log::info!("Reloaded templates from {}", self.dir.to_string_lossy());
+                }
+                Ok(false) => {}
+                Err(e) => log::error!("{}", e),
+            }
+        }
+    }
+}
+
+//hash of the template names and contents, symlinks are followed so ConfigMap updates are seen
+fn fingerprint(dir: &Path) -> Result<u64, TemplateError> {
+    let read_error = |e: std::io::Error| {
+        TemplateError::ParseError(dir.to_string_lossy().to_string(), e.to_string())
+    };
+    let mut files = std::fs::read_dir(dir)
+        .map_err(read_error)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "hcl")
+                && path.is_file()
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    let mut hasher = DefaultHasher::new();
+    for file in files {
+        file.hash(&mut hasher);
+        std::fs::read(&file).map_err(read_error)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+//tera hides the cause of parse and render errors in the source chain
+fn describe(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    message
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("Could not load templates from {0}: {1}")]
+    ParseError(String, String),
+    #[error("Could not render {0}: {1}")]
+    RenderError(String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context.insert("name", "demo-dev");
+        context
+    }
+
+    #[test]
+    fn test_render_from_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("role.yaml"), "role:{{ name }}").unwrap();
+
+        let templates = Templates::load(dir.path());
+        assert_eq!(
+            templates.render("role.yaml", &context()).unwrap(),
+            "role:demo-dev"
+        );
+        assert!(matches!(
+            templates.render("missing.yaml", &context()),
+            Err(TemplateError::RenderError(..))
+        ));
+    }
+
+    #[test]
+    fn test_reload_keeps_previous_templates_on_syntax_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("role.yaml");
+        std::fs::write(&template, "role:{{ name }}").unwrap();
+        let templates = Templates::load(dir.path());
+        assert!(!templates.reload().unwrap());
+
+        std::fs::write(&template, "role:{{ name ").unwrap();
+        assert!(matches!(
+            templates.reload(),
+            Err(TemplateError::ParseError(..))
+        ));
+        assert_eq!(
+            templates.render("role.yaml", &context()).unwrap(),
+            "role:demo-dev"
+        );
+        //the broken set is only reported once
+        assert!(!templates.reload().unwrap());
+
+        std::fs::write(&template, "group:{{ name }}").unwrap();
+        assert!(templates.reload().unwrap());
+        assert_eq!(
+            templates.render("role.yaml", &context()).unwrap(),
+            "group:demo-dev"
+        );
+    }
+
+    #[test]
+    fn test_load_does_not_fail_on_syntax_error() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("role.yaml"), "{% if %}").unwrap();
+
+        let templates = Templates::load(dir.path());
+        assert!(templates.render("role.yaml", &context()).is_err());
+    }
+}
@@ -0,0 +1,908 @@
+use async_trait::async_trait;
+use reqwest::{Client, Method, StatusCode};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::layout::{ProjectLayout, ProjectVars};
+use crate::project_crd::VaultSpec;
+use crate::vault_values::{AuthRole, GroupAlias, Policy, VaultValues};
+
+/// Vault access of a project: a policy bound to an external OIDC group and a
+/// Kubernetes auth role for the workloads of the project namespace
+#[derive(Debug, Clone)]
+pub struct VaultAccess {
+    pub policy_name: String,
+    pub policy: String,
+    pub group: String,
+    pub workload_policy_name: String,
+    pub workload_policy: String,
+    pub role: KubernetesRole,
+    /// Mount of the KV v2 engine holding the project secrets
+    pub kv_mount: String,
+    /// Secrets created empty when missing, relative to the KV mount
+    pub seed_secrets: Vec<String>,
+}
+
+/// Kubernetes auth role binding service accounts of the project namespace to the workload policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct KubernetesRole {
+    /// Mount of the Kubernetes auth method
+    pub mount: String,
+    pub name: String,
+    pub service_accounts: Vec<String>,
+    pub namespaces: Vec<String>,
+}
+
+impl VaultAccess {
+    //names derived from the project, policies are left empty to be rendered by the caller
+    pub fn for_project(project: &ProjectVars, spec: &VaultSpec) -> Self {
+        let secret_path = project.project_name.replace('-', "_");
+        let service_accounts = match spec.service_accounts.is_empty() {
+            true => vec!["*".to_string()],
+            false => spec.service_accounts.clone(),
+        };
+        Self {
+            policy_name: format!("{secret_path}_access"),
+            policy: String::new(),
+            group: project.google_group.clone(),
+            workload_policy_name: format!("{secret_path}_workload"),
+            workload_policy: String::new(),
+            role: KubernetesRole {
+                mount: std::env::var("VAULT_WORKLOAD_AUTH_MOUNT")
+                    .unwrap_or("kubernetes".to_string()),
+                name: project.project_name.clone(),
+                service_accounts,
+                namespaces: vec![project.project_name.clone()],
+            },
+            kv_mount: std::env::var("VAULT_KV_MOUNT").unwrap_or("secret".to_string()),
+            seed_secrets: spec
+                .seed_secrets
+                .iter()
+                .map(|secret| format!("{secret_path}/{}", secret.trim_matches('/')))
+                .collect(),
+        }
+    }
+}
+
+/// Where the operator configures Vault policies, groups and group aliases
+#[async_trait]
+pub trait VaultBackend: Send + Sync {
+    //grant access, returning the paths changed in the flux repository checkout
+    async fn grant(
+        &self,
+        repo_root: &Path,
+        layout: &ProjectLayout,
+        access: &VaultAccess,
+        dry_run: bool,
+    ) -> Result<Vec<PathBuf>, VaultError>;
+
+    //revoke access, returning the paths changed in the flux repository checkout
+    async fn revoke(
+        &self,
+        repo_root: &Path,
+        layout: &ProjectLayout,
+        access: &VaultAccess,
+        dry_run: bool,
+    ) -> Result<Vec<PathBuf>, VaultError>;
+
+    //reject a vault spec the backend cannot provision
+    fn check(&self, _spec: &VaultSpec) -> Result<(), VaultError> {
+        Ok(())
+    }
+}
+
+//backend selected by VAULT_BACKEND, gitops unless set to api
+pub fn from_env() -> Result<Arc<dyn VaultBackend>, VaultError> {
+    match std::env::var("VAULT_BACKEND").as_deref() {
+        Err(_) | Ok("gitops") => Ok(Arc::new(GitOpsValues)),
+        Ok("api") => Ok(Arc::new(VaultApi::from_env()?)),
+        Ok(backend) => Err(VaultError::ConfigError(format!(
+            "Unknown VAULT_BACKEND `{backend}`, expected gitops or api"
+        ))),
+    }
+}
+
+/// Edits `vault.externalConfig` in the Vault Helm values of the flux repository
+pub struct GitOpsValues;
+
+impl GitOpsValues {
+    fn edit(
+        repo_root: &Path,
+        layout: &ProjectLayout,
+        edit: impl FnOnce(&mut VaultValues) -> Result<(), crate::VaultValuesError>,
+    ) -> Result<Vec<PathBuf>, VaultError> {
+        let path = repo_root.join(&layout.vault_values);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| VaultError::GitOpsError(format!("Could not read {path:?}: {e}")))?;
+        let mut values =
+            VaultValues::parse(&contents).map_err(|e| VaultError::GitOpsError(e.to_string()))?;
+        edit(&mut values).map_err(|e| VaultError::GitOpsError(e.to_string()))?;
+        std::fs::write(&path, values.to_string())
+            .map_err(|e| VaultError::GitOpsError(format!("Could not write {path:?}: {e}")))?;
+        Ok(vec![layout.vault_values.clone()])
+    }
+}
+
+#[async_trait]
+impl VaultBackend for GitOpsValues {
+    async fn grant(
+        &self,
+        repo_root: &Path,
+        layout: &ProjectLayout,
+        access: &VaultAccess,
+        _dry_run: bool,
+    ) -> Result<Vec<PathBuf>, VaultError> {
+        Self::edit(repo_root, layout, |values| {
+            values.add_policy(&Policy {
+                name: access.policy_name.clone(),
+                rules: access.policy.clone(),
+            })?;
+            values.add_group_policy(&access.group, &access.policy_name)?;
+            values.add_group_alias(&GroupAlias {
+                name: access.group.clone(),
+                mountpath: "oidc".to_string(),
+                group: access.group.clone(),
+            })?;
+            values.add_policy(&Policy {
+                name: access.workload_policy_name.clone(),
+                rules: access.workload_policy.clone(),
+            })?;
+            values.add_auth_role(
+                &access.role.mount,
+                &AuthRole {
+                    name: access.role.name.clone(),
+                    bound_service_account_names: access.role.service_accounts.clone(),
+                    bound_service_account_namespaces: access.role.namespaces.clone(),
+                    policies: vec![access.workload_policy_name.clone()],
+                },
+            )?;
+            Ok(())
+        })
+    }
+
+    fn check(&self, spec: &VaultSpec) -> Result<(), VaultError> {
+        if spec.seed_secrets.is_empty() {
+            return Ok(());
+        }
+        Err(VaultError::ConfigError(
+            "seedSecrets is only supported by the api Vault backend".to_string(),
+        ))
+    }
+
+    async fn revoke(
+        &self,
+        repo_root: &Path,
+        layout: &ProjectLayout,
+        access: &VaultAccess,
+        _dry_run: bool,
+    ) -> Result<Vec<PathBuf>, VaultError> {
+        Self::edit(repo_root, layout, |values| {
+            values.remove_policy(&access.policy_name)?;
+            values.remove_group_policy(&access.policy_name)?;
+            if !values.has_group(&access.group)? {
+                values.remove_group_alias(&access.group)?;
+            }
+            values.remove_auth_role(&access.role.mount, &access.role.name)?;
+            values.remove_policy(&access.workload_policy_name)
+        })
+    }
+}
+
+/// How the operator logs in to Vault
+#[derive(Clone)]
+pub enum VaultAuth {
+    Token(String),
+    /// Kubernetes auth method with the operator service account token
+    Kubernetes {
+        mount: String,
+        role: String,
+        jwt_path: PathBuf,
+    },
+}
+
+/// Configures Vault directly through its HTTP API
+#[derive(Clone)]
+pub struct VaultApi {
+    pub client: Client,
+    pub vault_addr: String,
+    pub auth: VaultAuth,
+    /// Mount of the OIDC auth method the group aliases point at
+    pub oidc_mount: String,
+}
+
+impl std::fmt::Debug for VaultApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultApi")
+            .field("vault_addr", &self.vault_addr)
+            .field("oidc_mount", &self.oidc_mount)
+            .finish()
+    }
+}
+
+impl VaultApi {
+    pub fn new(vault_addr: String, auth: VaultAuth, oidc_mount: String) -> Self {
+        Self {
+            client: Client::new(),
+            vault_addr,
+            auth,
+            oidc_mount,
+        }
+    }
+
+    //VAULT_ADDR with VAULT_TOKEN, or kubernetes auth with VAULT_AUTH_ROLE
+    pub fn from_env() -> Result<Self, VaultError> {
+        let vault_addr = std::env::var("VAULT_ADDR")
+            .map_err(|_| VaultError::ConfigError("VAULT_ADDR not set".to_string()))?;
+        let auth =
+            match (
+                std::env::var("VAULT_TOKEN"),
+                std::env::var("VAULT_AUTH_ROLE"),
+            ) {
+                (Ok(token), _) => VaultAuth::Token(token),
+                (_, Ok(role)) => VaultAuth::Kubernetes {
+                    mount: std::env::var("VAULT_AUTH_MOUNT").unwrap_or("kubernetes".to_string()),
+                    role,
+                    jwt_path: PathBuf::from(std::env::var("VAULT_AUTH_JWT_PATH").unwrap_or(
+                        "/var/run/secrets/kubernetes.io/serviceaccount/token".to_string(),
+                    )),
+                },
+                _ => {
+                    return Err(VaultError::ConfigError(
+                        "VAULT_TOKEN or VAULT_AUTH_ROLE must be set".to_string(),
+                    ))
+                }
+            };
+        let oidc_mount = std::env::var("VAULT_OIDC_MOUNT").unwrap_or("oidc".to_string());
+        Ok(Self::new(vault_addr, auth, oidc_mount))
+    }
+
+    async fn token(&self) -> Result<String, VaultError> {
+        match &self.auth {
+            VaultAuth::Token(token) => Ok(token.clone()),
+            VaultAuth::Kubernetes {
+                mount,
+                role,
+                jwt_path,
+            } => {
+                let jwt = std::fs::read_to_string(jwt_path).map_err(|e| {
+                    VaultError::ApiError(format!("Could not read {jwt_path:?}: {e}"))
+                })?;
+                let login = self
+                    .send(
+                        Method::POST,
+                        &format!("auth/{mount}/login"),
+                        None,
+                        Some(json!({ "role": role, "jwt": jwt.trim() })),
+                    )
+                    .await?
+                    .ok_or_else(|| VaultError::ApiError("Empty login response".to_string()))?;
+                login["auth"]["client_token"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| VaultError::ApiError("Login returned no token".to_string()))
+            }
+        }
+    }
+
+    //call the api, none for 404 and empty responses
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Result<Option<Value>, VaultError> {
+        let url = format!("{}/v1/{path}", self.vault_addr);
+        let mut request = self.client.request(method.clone(), &url);
+        if let Some(token) = token {
+            request = request.header("X-Vault-Token", token);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| VaultError::ApiError(format!("{method} {path}: {e}")))?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response
+            .text()
+            .await
+            .map_err(|e| VaultError::ApiError(format!("{method} {path}: {e}")))?;
+        if !status.is_success() {
+            return Err(VaultError::ApiError(format!(
+                "{method} {path} returned {status}: {text}"
+            )));
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| VaultError::ApiError(format!("{method} {path}: {e}")))
+    }
+
+    async fn mount_accessor(&self, token: &str) -> Result<String, VaultError> {
+        let mounts = self
+            .send(Method::GET, "sys/auth", Some(token), None)
+            .await?
+            .unwrap_or_default();
+        let mount = format!("{}/", self.oidc_mount.trim_end_matches('/'));
+        //newer versions nest the mounts under data
+        let mounts = if mounts.get("data").is_some() {
+            &mounts["data"]
+        } else {
+            &mounts
+        };
+        mounts[&mount]["accessor"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| VaultError::ApiError(format!("Auth mount {mount} not found")))
+    }
+
+    async fn group(&self, token: &str, name: &str) -> Result<Option<Value>, VaultError> {
+        Ok(self
+            .send(
+                Method::GET,
+                &format!("identity/group/name/{name}"),
+                Some(token),
+                None,
+            )
+            .await?
+            .map(|group| group["data"].clone()))
+    }
+
+    //add the policy to the external group, creating the group and its alias when missing
+    async fn grant_group(&self, token: &str, access: &VaultAccess) -> Result<(), VaultError> {
+        let group_path = format!("identity/group/name/{}", access.group);
+        let group_id = match self.group(token, &access.group).await? {
+            Some(group) => {
+                let mut policies = policies_of(&group);
+                if !policies.contains(&access.policy_name) {
+                    policies.push(access.policy_name.clone());
+                    self.send(
+                        Method::POST,
+                        &group_path,
+                        Some(token),
+                        Some(json!({ "policies": policies })),
+                    )
+                    .await?;
+                }
+                if group["alias"]["name"].as_str().is_some() {
+                    return Ok(());
+                }
+                group["id"].as_str().map(str::to_string)
+            }
+            None => self
+                .send(
+                    Method::POST,
+                    &group_path,
+                    Some(token),
+                    Some(json!({
+                        "type": "external",
+                        "policies": [access.policy_name],
+                    })),
+                )
+                .await?
+                .and_then(|group| group["data"]["id"].as_str().map(str::to_string)),
+        }
+        .ok_or_else(|| VaultError::ApiError(format!("No id for group {}", access.group)))?;
+
+        let mount_accessor = self.mount_accessor(token).await?;
+        self.send(
+            Method::POST,
+            "identity/group-alias",
+            Some(token),
+            Some(json!({
+                "name": access.group,
+                "mount_accessor": mount_accessor,
+                "canonical_id": group_id,
+            })),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+fn policies_of(group: &Value) -> Vec<String> {
+    group["policies"]
+        .as_array()
+        .map(|policies| {
+            policies
+                .iter()
+                .filter_map(|policy| policy.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl VaultBackend for VaultApi {
+    async fn grant(
+        &self,
+        _repo_root: &Path,
+        _layout: &ProjectLayout,
+        access: &VaultAccess,
+        dry_run: bool,
+    ) -> Result<Vec<PathBuf>, VaultError> {
+        if dry_run {
+            log::info!(
+                "Would grant Vault policy {} to group {}",
+                access.policy_name,
+                access.group
+            );
+            return Ok(vec![]);
+        }
+        let token = self.token().await?;
+        self.send(
+            Method::PUT,
+            &format!("sys/policy/{}", access.policy_name),
+            Some(&token),
+            Some(json!({ "policy": access.policy })),
+        )
+        .await?;
+
+        self.grant_group(&token, access).await?;
+
+        self.send(
+            Method::PUT,
+            &format!("sys/policy/{}", access.workload_policy_name),
+            Some(&token),
+            Some(json!({ "policy": access.workload_policy })),
+        )
+        .await?;
+        self.send(
+            Method::POST,
+            &format!("auth/{}/role/{}", access.role.mount, access.role.name),
+            Some(&token),
+            Some(json!({
+                "bound_service_account_names": access.role.service_accounts,
+                "bound_service_account_namespaces": access.role.namespaces,
+                "token_policies": [access.workload_policy_name],
+            })),
+        )
+        .await?;
+
+        //an existing secret is never overwritten
+        for secret in &access.seed_secrets {
+            let path = format!("{}/data/{secret}", access.kv_mount);
+            if self
+                .send(Method::GET, &path, Some(&token), None)
+                .await?
+                .is_none()
+            {
+                self.send(
+                    Method::POST,
+                    &path,
+                    Some(&token),
+                    Some(json!({ "options": { "cas": 0 }, "data": {} })),
+                )
+                .await?;
+            }
+        }
+        Ok(vec![])
+    }
+
+    async fn revoke(
+        &self,
+        _repo_root: &Path,
+        _layout: &ProjectLayout,
+        access: &VaultAccess,
+        dry_run: bool,
+    ) -> Result<Vec<PathBuf>, VaultError> {
+        if dry_run {
+            log::info!(
+                "Would revoke Vault policy {} from group {}",
+                access.policy_name,
+                access.group
+            );
+            return Ok(vec![]);
+        }
+        let token = self.token().await?;
+        if let Some(group) = self.group(&token, &access.group).await? {
+            let group_path = format!("identity/group/name/{}", access.group);
+            let mut policies = policies_of(&group);
+            policies.retain(|policy| policy != &access.policy_name);
+            //deleting the group removes its alias too
+            if policies.is_empty() {
+                self.send(Method::DELETE, &group_path, Some(&token), None)
+                    .await?;
+            } else {
+                self.send(
+                    Method::POST,
+                    &group_path,
+                    Some(&token),
+                    Some(json!({ "policies": policies })),
+                )
+                .await?;
+            }
+        }
+        self.send(
+            Method::DELETE,
+            &format!("sys/policy/{}", access.policy_name),
+            Some(&token),
+            None,
+        )
+        .await?;
+        //seeded secrets are kept, they may hold data written since
+        self.send(
+            Method::DELETE,
+            &format!("auth/{}/role/{}", access.role.mount, access.role.name),
+            Some(&token),
+            None,
+        )
+        .await?;
+        self.send(
+            Method::DELETE,
+            &format!("sys/policy/{}", access.workload_policy_name),
+            Some(&token),
+            None,
+        )
+        .await?;
+        Ok(vec![])
+    }
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum VaultError {
+    #[error("Invalid Vault backend config: {0}")]
+    ConfigError(String),
+    #[error("Could not edit Vault values: {0}")]
+    GitOpsError(String),
+    #[error("Vault API error: {0}")]
+    ApiError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    fn access() -> VaultAccess {
+        let project = ProjectVars {
+            project_name: "demo-dev".to_string(),
+            project_id: "demo".to_string(),
+            environment_type: "dev".to_string(),
+            namespace: "default".to_string(),
+            google_group: "demo@kyotu.tech".to_string(),
+        };
+        let mut access = VaultAccess::for_project(&project, &VaultSpec::default());
+        access.policy = "path \"secret/demo_dev/*\" {\n  capabilities = [\"read\"]\n}".to_string();
+        access.workload_policy =
+            "path \"secret/demo_dev/*\" {\n  capabilities = [\"read\", \"list\"]\n}".to_string();
+        access
+    }
+
+    //workload policy and auth role written after the group
+    async fn workload_mocks(server: &mut mockito::Server) -> (mockito::Mock, mockito::Mock) {
+        let policy = server
+            .mock("PUT", "/v1/sys/policy/demo_dev_workload")
+            .match_body(Matcher::Json(json!({ "policy": access().workload_policy })))
+            .with_status(204)
+            .create_async()
+            .await;
+        let role = server
+            .mock("POST", "/v1/auth/kubernetes/role/demo-dev")
+            .match_body(Matcher::Json(json!({
+                "bound_service_account_names": ["*"],
+                "bound_service_account_namespaces": ["demo-dev"],
+                "token_policies": ["demo_dev_workload"],
+            })))
+            .with_status(204)
+            .create_async()
+            .await;
+        (policy, role)
+    }
+
+    fn layout() -> ProjectLayout {
+        ProjectLayout {
+            project_dir: PathBuf::from("manifests/demo-dev"),
+            application: PathBuf::from("applications/demo-dev.yaml"),
+            vault_values: PathBuf::from("rbac_values.yaml"),
+            argo_rbac: PathBuf::from("rbac.yaml"),
+        }
+    }
+
+    fn api(server: &mockito::Server) -> VaultApi {
+        VaultApi::new(
+            server.url(),
+            VaultAuth::Token("root".to_string()),
+            "oidc".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_gitops_values_round_trip() {
+        let values = include_str!("../tests/fixtures/vault_rbac_values.yaml");
+        let checkout = tempfile::tempdir().unwrap();
+        std::fs::write(checkout.path().join("rbac_values.yaml"), values).unwrap();
+
+        let changed = GitOpsValues
+            .grant(checkout.path(), &layout(), &access(), false)
+            .await
+            .unwrap();
+        assert_eq!(changed, vec![PathBuf::from("rbac_values.yaml")]);
+        let granted = std::fs::read_to_string(checkout.path().join("rbac_values.yaml")).unwrap();
+        assert!(granted.contains("- name: demo_dev_access"));
+        assert!(granted.contains("- name: demo@kyotu.tech"));
+        assert!(granted.contains("- name: demo_dev_workload"));
+        assert!(granted.contains("          - name: demo-dev\n"));
+
+        GitOpsValues
+            .revoke(checkout.path(), &layout(), &access(), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(checkout.path().join("rbac_values.yaml")).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn test_only_api_seeds_secrets() {
+        let spec = VaultSpec {
+            seed_secrets: vec!["app".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            GitOpsValues.check(&spec),
+            Err(VaultError::ConfigError(_))
+        ));
+        GitOpsValues.check(&VaultSpec::default()).unwrap();
+        let api = VaultApi::new(
+            "http://vault:8200".to_string(),
+            VaultAuth::Token("root".to_string()),
+            "oidc".to_string(),
+        );
+        api.check(&spec).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_api_grant_creates_group_and_alias() {
+        let mut server = mockito::Server::new_async().await;
+        let policy = server
+            .mock("PUT", "/v1/sys/policy/demo_dev_access")
+            .match_header("X-Vault-Token", "root")
+            .match_body(Matcher::Json(json!({ "policy": access().policy })))
+            .with_status(204)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/identity/group/name/demo@kyotu.tech")
+            .with_status(404)
+            .create_async()
+            .await;
+        let group = server
+            .mock("POST", "/v1/identity/group/name/demo@kyotu.tech")
+            .match_body(Matcher::Json(json!({
+                "type": "external",
+                "policies": ["demo_dev_access"],
+            })))
+            .with_status(200)
+            .with_body(r#"{"data": {"id": "group-id", "name": "demo@kyotu.tech"}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/sys/auth")
+            .with_status(200)
+            .with_body(r#"{"data": {"oidc/": {"accessor": "auth_oidc_1234", "type": "oidc"}}}"#)
+            .create_async()
+            .await;
+        let alias = server
+            .mock("POST", "/v1/identity/group-alias")
+            .match_body(Matcher::Json(json!({
+                "name": "demo@kyotu.tech",
+                "mount_accessor": "auth_oidc_1234",
+                "canonical_id": "group-id",
+            })))
+            .with_status(200)
+            .with_body(r#"{"data": {"id": "alias-id"}}"#)
+            .create_async()
+            .await;
+        let (workload_policy, role) = workload_mocks(&mut server).await;
+
+        let changed = api(&server)
+            .grant(Path::new("unused"), &layout(), &access(), false)
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+        policy.assert_async().await;
+        group.assert_async().await;
+        alias.assert_async().await;
+        workload_policy.assert_async().await;
+        role.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_api_grant_adds_policy_to_existing_group() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("PUT", "/v1/sys/policy/demo_dev_access")
+            .with_status(204)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/identity/group/name/demo@kyotu.tech")
+            .with_status(200)
+            .with_body(
+                r#"{"data": {"id": "group-id", "policies": ["shop_dev_access"], "alias": {"name": "demo@kyotu.tech"}}}"#,
+            )
+            .create_async()
+            .await;
+        let group = server
+            .mock("POST", "/v1/identity/group/name/demo@kyotu.tech")
+            .match_body(Matcher::Json(json!({
+                "policies": ["shop_dev_access", "demo_dev_access"],
+            })))
+            .with_status(204)
+            .create_async()
+            .await;
+        let alias = server
+            .mock("POST", "/v1/identity/group-alias")
+            .expect(0)
+            .create_async()
+            .await;
+        workload_mocks(&mut server).await;
+
+        api(&server)
+            .grant(Path::new("unused"), &layout(), &access(), false)
+            .await
+            .unwrap();
+        group.assert_async().await;
+        alias.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_api_revoke_deletes_group_without_policies() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/v1/identity/group/name/demo@kyotu.tech")
+            .with_status(200)
+            .with_body(r#"{"data": {"id": "group-id", "policies": ["demo_dev_access"]}}"#)
+            .create_async()
+            .await;
+        let group = server
+            .mock("DELETE", "/v1/identity/group/name/demo@kyotu.tech")
+            .with_status(204)
+            .create_async()
+            .await;
+        let policy = server
+            .mock("DELETE", "/v1/sys/policy/demo_dev_access")
+            .with_status(204)
+            .create_async()
+            .await;
+        let role = server
+            .mock("DELETE", "/v1/auth/kubernetes/role/demo-dev")
+            .with_status(204)
+            .create_async()
+            .await;
+        let workload_policy = server
+            .mock("DELETE", "/v1/sys/policy/demo_dev_workload")
+            .with_status(204)
+            .create_async()
+            .await;
+
+        api(&server)
+            .revoke(Path::new("unused"), &layout(), &access(), false)
+            .await
+            .unwrap();
+        group.assert_async().await;
+        policy.assert_async().await;
+        role.assert_async().await;
+        workload_policy.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_api_seeds_missing_secrets_only() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("PUT", "/v1/sys/policy/demo_dev_access")
+            .with_status(204)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/identity/group/name/demo@kyotu.tech")
+            .with_status(200)
+            .with_body(
+                r#"{"data": {"id": "group-id", "policies": ["demo_dev_access"], "alias": {"name": "demo@kyotu.tech"}}}"#,
+            )
+            .create_async()
+            .await;
+        workload_mocks(&mut server).await;
+        server
+            .mock("GET", "/v1/secret/data/demo_dev/app")
+            .with_status(404)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/secret/data/demo_dev/db")
+            .with_status(200)
+            .with_body(r#"{"data": {"data": {"password": "kept"}}}"#)
+            .create_async()
+            .await;
+        let seeded = server
+            .mock("POST", "/v1/secret/data/demo_dev/app")
+            .match_body(Matcher::Json(
+                json!({ "options": { "cas": 0 }, "data": {} }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"version": 1}}"#)
+            .create_async()
+            .await;
+        let kept = server
+            .mock("POST", "/v1/secret/data/demo_dev/db")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let access = VaultAccess {
+            seed_secrets: vec!["demo_dev/app".to_string(), "demo_dev/db".to_string()],
+            ..access()
+        };
+        api(&server)
+            .grant(Path::new("unused"), &layout(), &access, false)
+            .await
+            .unwrap();
+        seeded.assert_async().await;
+        kept.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_api_kubernetes_login_and_errors() {
+        let mut server = mockito::Server::new_async().await;
+        let jwt = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(jwt.path(), "service-account-jwt\n").unwrap();
+        let login = server
+            .mock("POST", "/v1/auth/kubernetes/login")
+            .match_body(Matcher::Json(json!({
+                "role": "kyotu-project-operator",
+                "jwt": "service-account-jwt",
+            })))
+            .with_status(200)
+            .with_body(r#"{"auth": {"client_token": "login-token"}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("PUT", "/v1/sys/policy/demo_dev_access")
+            .match_header("X-Vault-Token", "login-token")
+            .with_status(403)
+            .with_body(r#"{"errors": ["permission denied"]}"#)
+            .create_async()
+            .await;
+
+        let api = VaultApi::new(
+            server.url(),
+            VaultAuth::Kubernetes {
+                mount: "kubernetes".to_string(),
+                role: "kyotu-project-operator".to_string(),
+                jwt_path: jwt.path().to_path_buf(),
+            },
+            "oidc".to_string(),
+        );
+        let result = api
+            .grant(Path::new("unused"), &layout(), &access(), false)
+            .await;
+        login.assert_async().await;
+        assert!(matches!(result, Err(VaultError::ApiError(message)) if message.contains("403")));
+    }
+
+    #[tokio::test]
+    async fn test_api_dry_run_makes_no_calls() {
+        let mut server = mockito::Server::new_async().await;
+        let any = server
+            .mock("PUT", Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+        api(&server)
+            .grant(Path::new("unused"), &layout(), &access(), true)
+            .await
+            .unwrap();
+        any.assert_async().await;
+    }
+}
@@ -0,0 +1,291 @@
+use hcl::{Body, Expression, Structure};
+use tera::Context;
+
+use crate::layout::ProjectVars;
+use crate::project_crd::VaultPath;
+use crate::templates::{TemplateError, Templates};
+
+/// Capabilities Vault accepts in a path rule
+const CAPABILITIES: [&str; 9] = [
+    "create",
+    "read",
+    "update",
+    "patch",
+    "delete",
+    "list",
+    "sudo",
+    "deny",
+    "subscribe",
+];
+
+/// Capabilities extra paths may hold in prod, where access is read only
+const PROD_CAPABILITIES: [&str; 2] = ["read", "list"];
+
+/// Attributes Vault accepts in a path rule
+const PATH_ATTRIBUTES: [&str; 6] = [
+    "capabilities",
+    "required_parameters",
+    "allowed_parameters",
+    "denied_parameters",
+    "min_wrapping_ttl",
+    "max_wrapping_ttl",
+];
+
+//render the policy of a project from vault_policy_<environment>.hcl, falling back to vault_policy.hcl
+pub fn render(
+    templates: &Templates,
+    project: &ProjectVars,
+    paths: &[VaultPath],
+) -> Result<String, VaultPolicyError> {
+    render_template(templates, "vault_policy", project, paths)
+}
+
+//render the policy of the project workloads from vault_workload_policy_<environment>.hcl, falling back to vault_workload_policy.hcl
+pub fn render_workload(
+    templates: &Templates,
+    project: &ProjectVars,
+    paths: &[VaultPath],
+) -> Result<String, VaultPolicyError> {
+    render_template(templates, "vault_workload_policy", project, paths)
+}
+
+fn render_template(
+    templates: &Templates,
+    name: &str,
+    project: &ProjectVars,
+    paths: &[VaultPath],
+) -> Result<String, VaultPolicyError> {
+    check_paths(&project.environment_type, paths)?;
+    let environment_template = format!("{name}_{}.hcl", project.environment_type);
+    let default_template = format!("{name}.hcl");
+    let template = if templates.contains(&environment_template) {
+        environment_template.as_str()
+    } else {
+        default_template.as_str()
+    };
+    let mut context = Context::from_serialize(project).map_err(|e| {
+        VaultPolicyError::TemplateError(TemplateError::RenderError(
+            template.to_string(),
+            e.to_string(),
+        ))
+    })?;
+    context.insert("secret_path", &project.project_name.replace('-', "_"));
+    context.insert("paths", paths);
+    let policy = templates
+        .render(template, &context)
+        .map_err(VaultPolicyError::TemplateError)?;
+    let policy = policy.trim().to_string();
+    validate(&policy)?;
+    Ok(policy)
+}
+
+//reject extra paths with capabilities beyond read and list in prod
+pub fn check_paths(environment_type: &str, paths: &[VaultPath]) -> Result<(), VaultPolicyError> {
+    if environment_type != "prod" {
+        return Ok(());
+    }
+    for path in paths {
+        if let Some(capability) = path
+            .capabilities
+            .iter()
+            .find(|capability| !PROD_CAPABILITIES.contains(&capability.as_str()))
+        {
+            return Err(VaultPolicyError::InvalidPolicy(format!(
+                "capability `{capability}` of path `{}` is not allowed in prod, only read and list are",
+                path.path
+            )));
+        }
+    }
+    Ok(())
+}
+
+//check the policy only holds path rules Vault would accept
+pub fn validate(policy: &str) -> Result<(), VaultPolicyError> {
+    let body: Body =
+        hcl::parse(policy).map_err(|e| VaultPolicyError::InvalidPolicy(e.to_string()))?;
+    for structure in body.iter() {
+        let block = match structure {
+            Structure::Block(block) if block.identifier() == "path" => block,
+            Structure::Block(block) => {
+                return Err(VaultPolicyError::InvalidPolicy(format!(
+                    "unexpected block `{}`",
+                    block.identifier()
+                )))
+            }
+            Structure::Attribute(attribute) => {
+                return Err(VaultPolicyError::InvalidPolicy(format!(
+                    "unexpected attribute `{}` outside of a path",
+                    attribute.key()
+                )))
+            }
+        };
+        let path = match block.labels() {
+            [label] => label.as_str(),
+            _ => {
+                return Err(VaultPolicyError::InvalidPolicy(
+                    "path blocks take exactly one label".to_string(),
+                ))
+            }
+        };
+        let mut has_capabilities = false;
+        for rule in block.body().iter() {
+            let Structure::Attribute(attribute) = rule else {
+                return Err(VaultPolicyError::InvalidPolicy(format!(
+                    "unexpected block in path `{path}`"
+                )));
+            };
+            if !PATH_ATTRIBUTES.contains(&attribute.key()) {
+                return Err(VaultPolicyError::InvalidPolicy(format!(
+                    "unexpected attribute `{}` in path `{path}`",
+                    attribute.key()
+                )));
+            }
+            if attribute.key() != "capabilities" {
+                continue;
+            }
+            has_capabilities = true;
+            let Expression::Array(capabilities) = attribute.expr() else {
+                return Err(VaultPolicyError::InvalidPolicy(format!(
+                    "capabilities of path `{path}` must be a list"
+                )));
+            };
+            for capability in capabilities {
+                match capability {
+                    Expression::String(capability)
+                        if CAPABILITIES.contains(&capability.as_str()) => {}
+                    capability => {
+                        return Err(VaultPolicyError::InvalidPolicy(format!(
+                            "unknown capability `{capability}` in path `{path}`"
+                        )))
+                    }
+                }
+            }
+        }
+        if !has_capabilities {
+            return Err(VaultPolicyError::InvalidPolicy(format!(
+                "path `{path}` has no capabilities"
+            )));
+        }
+    }
+    Ok(())
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum VaultPolicyError {
+    #[error(transparent)]
+    TemplateError(TemplateError),
+    #[error("Invalid Vault policy: {0}")]
+    InvalidPolicy(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(environment_type: &str) -> ProjectVars {
+        ProjectVars {
+            project_name: format!("demo-{environment_type}"),
+            project_id: "demo".to_string(),
+            environment_type: environment_type.to_string(),
+            namespace: "default".to_string(),
+            google_group: "demo@kyotu.tech".to_string(),
+        }
+    }
+
+    fn transit() -> VaultPath {
+        VaultPath {
+            path: "transit/encrypt/demo".to_string(),
+            capabilities: vec!["update".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_render_per_environment() {
+        let templates = Templates::load("templates");
+        assert_eq!(
+            render(&templates, &project("dev"), &[]).unwrap(),
+            "path \"secret/demo_dev/*\" {\n  capabilities = [\"create\", \"read\", \"update\", \"delete\", \"list\"]\n}"
+        );
+        assert_eq!(
+            render(&templates, &project("prod"), &[]).unwrap(),
+            "path \"secret/demo_prod/*\" {\n  capabilities = [\"read\", \"list\"]\n}"
+        );
+    }
+
+    #[test]
+    fn test_render_workload() {
+        let templates = Templates::load("templates");
+        assert_eq!(
+            render_workload(&templates, &project("dev"), &[transit()]).unwrap(),
+            "path \"secret/demo_dev/*\" {\n  capabilities = [\"read\", \"list\"]\n}\n\npath \"transit/encrypt/demo\" {\n  capabilities = [\"update\"]\n}"
+        );
+    }
+
+    #[test]
+    fn test_render_extra_paths() {
+        let templates = Templates::load("templates");
+        let policy = render(&templates, &project("dev"), &[transit()]).unwrap();
+        assert!(
+            policy.ends_with("path \"transit/encrypt/demo\" {\n  capabilities = [\"update\"]\n}")
+        );
+        let database = VaultPath {
+            path: "database/creds/demo".to_string(),
+            capabilities: vec!["read".to_string()],
+        };
+        let policy = render(&templates, &project("prod"), &[database]).unwrap();
+        assert!(policy.ends_with("path \"database/creds/demo\" {\n  capabilities = [\"read\"]\n}"));
+    }
+
+    #[test]
+    fn test_prod_extra_paths_are_read_only() {
+        let templates = Templates::load("templates");
+        for render in [render, render_workload] {
+            assert!(matches!(
+                render(&templates, &project("prod"), &[transit()]),
+                Err(VaultPolicyError::InvalidPolicy(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_render_rejects_invalid_extra_paths() {
+        let templates = Templates::load("templates");
+        let unknown = VaultPath {
+            capabilities: vec!["write".to_string()],
+            ..transit()
+        };
+        assert!(matches!(
+            render(&templates, &project("dev"), &[unknown]),
+            Err(VaultPolicyError::InvalidPolicy(_))
+        ));
+        //quotes in a path are escaped rather than closing the label
+        let injected = VaultPath {
+            path: "x\" {}\npath \"sys/*".to_string(),
+            ..transit()
+        };
+        let policy = render(&templates, &project("dev"), &[injected]).unwrap();
+        assert_eq!(policy.matches("\npath ").count(), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        validate(
+            "path \"secret/*\" {\n  capabilities = [\"read\"]\n  max_wrapping_ttl = \"1h\"\n}",
+        )
+        .unwrap();
+        for invalid in [
+            "path \"secret/*\" {\n  capabilities = [\"read\"]\n",
+            "path \"secret/*\" {\n  policy = \"read\"\n}",
+            "path \"secret/*\" {}",
+            "path {\n  capabilities = [\"read\"]\n}",
+            "capabilities = [\"read\"]",
+            "path \"secret/*\" {\n  capabilities = \"read\"\n}",
+        ] {
+            assert!(
+                matches!(validate(invalid), Err(VaultPolicyError::InvalidPolicy(_))),
+                "{invalid}"
+            );
+        }
+    }
+}
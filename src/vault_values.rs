@@ -0,0 +1,705 @@
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::ops::Range;
+
+use crate::yaml_lines::{indent, key_of, significant, strip_comment};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct VaultConfig {
+    vault: Vault,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Vault {
+    external_config: ExternalConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ExternalConfig {
+    policies: Vec<Policy>,
+    groups: Vec<Group>,
+    #[serde(rename = "group-aliases")]
+    group_aliases: Vec<GroupAlias>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct Policy {
+    pub name: String,
+    pub rules: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Group {
+    pub name: String,
+    pub policies: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    #[serde(rename = "type")]
+    pub group_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct Metadata {
+    pub privileged: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct GroupAlias {
+    pub name: String,
+    pub mountpath: String,
+    pub group: String,
+}
+
+/// Kubernetes auth role binding service accounts to policies
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct AuthRole {
+    pub name: String,
+    pub bound_service_account_names: Vec<String>,
+    pub bound_service_account_namespaces: Vec<String>,
+    pub policies: Vec<String>,
+}
+
+/// Auth method configured by the Vault configurer
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Auth {
+    #[serde(rename = "type")]
+    auth_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    roles: Vec<AuthRole>,
+}
+
+/// Vault Helm values edited in place
+///
+/// Only entries of `vault.externalConfig.policies`, `groups`, `group-aliases` and the roles of
+/// the kubernetes `auth` methods are touched,
+/// every other line, comment and unknown field is written back as it was read.
+pub(crate) struct VaultValues {
+    lines: Vec<String>,
+}
+
+/// Block sequence found in the values
+struct Sequence {
+    key_line: usize,
+    key_indent: usize,
+    /// flow sequence written on the key line, such as `key: []` or `key: [a, b]`
+    inline: Option<Vec<String>>,
+    dash_indent: Option<usize>,
+    items: Vec<Range<usize>>,
+}
+
+impl VaultValues {
+    pub fn parse(yaml: &str) -> Result<Self, VaultValuesError> {
+        serde_yaml::from_str::<VaultConfig>(yaml)
+            .map_err(|e| VaultValuesError::ParseError(e.to_string()))?;
+        Ok(Self {
+            lines: yaml.split('\n').map(str::to_string).collect(),
+        })
+    }
+
+    //add a policy, replacing the rules of one with the same name
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<(), VaultValuesError> {
+        let policies = self.sequence("policies")?;
+        match self.find_in(&policies, &policy.name) {
+            Some(item) => self.set_field(&policies, item, "rules", &policy.rules),
+            None => self.push(&policies, policy),
+        }
+    }
+
+    //add a policy to a group, creating an external group when missing
+    pub fn add_group_policy(&mut self, group: &str, policy: &str) -> Result<(), VaultValuesError> {
+        let Some(item) = self.find_item("groups", group)? else {
+            return self.push_item(
+                "groups",
+                &Group {
+                    name: group.to_string(),
+                    policies: vec![policy.to_string()],
+                    metadata: None,
+                    group_type: "external".to_string(),
+                },
+            );
+        };
+        let policies = self.child_sequence(item, "policies")?;
+        let mut names = self.scalars(&policies);
+        if !names.iter().any(|name| name == policy) {
+            match policies.inline {
+                Some(_) if !names.is_empty() => {
+                    names.push(policy.to_string());
+                    self.set_inline(&policies, &names);
+                }
+                _ => self.push(&policies, &policy.to_string())?,
+            }
+        }
+        Ok(())
+    }
+
+    //add a group alias unless one with the same name exists
+    pub fn add_group_alias(&mut self, alias: &GroupAlias) -> Result<(), VaultValuesError> {
+        if self.find_item("group-aliases", &alias.name)?.is_none() {
+            self.push_item("group-aliases", alias)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_policy(&mut self, name: &str) -> Result<(), VaultValuesError> {
+        if let Some(item) = self.find_item("policies", name)? {
+            let policies = self.sequence("policies")?;
+            self.remove(&policies, item);
+        }
+        Ok(())
+    }
+
+    //remove a policy from every group, dropping groups left without policies
+    pub fn remove_group_policy(&mut self, policy: &str) -> Result<(), VaultValuesError> {
+        let groups = self.sequence("groups")?;
+        for item in groups.items.iter().rev() {
+            let policies = self.child_sequence(item.clone(), "policies")?;
+            let names = self.scalars(&policies);
+            let Some(position) = names.iter().position(|name| name == policy) else {
+                continue;
+            };
+            if names.len() == 1 {
+                self.remove(&groups, item.clone());
+            } else if policies.inline.is_some() {
+                let mut names = names;
+                names.remove(position);
+                self.set_inline(&policies, &names);
+            } else {
+                self.remove(&policies, policies.items[position].clone());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_group(&self, name: &str) -> Result<bool, VaultValuesError> {
+        Ok(self.find_item("groups", name)?.is_some())
+    }
+
+    pub fn remove_group_alias(&mut self, name: &str) -> Result<(), VaultValuesError> {
+        if let Some(item) = self.find_item("group-aliases", name)? {
+            let aliases = self.sequence("group-aliases")?;
+            self.remove(&aliases, item);
+        }
+        Ok(())
+    }
+
+    //add a role to the kubernetes auth method mounted at mount, replacing the bindings of one with the same name
+    pub fn add_auth_role(&mut self, mount: &str, role: &AuthRole) -> Result<(), VaultValuesError> {
+        if self.child(self.external_config()?, "auth").is_none() {
+            let config = self.external_config()?;
+            let first = config
+                .clone()
+                .find(|&line| significant(&self.lines[line]))
+                .unwrap();
+            let at = self.last_significant(config) + 1;
+            self.lines.insert(
+                at,
+                format!("{}auth: []", " ".repeat(indent(&self.lines[first]))),
+            );
+        }
+        let Some(item) = self.find_auth(mount)? else {
+            return self.push_item(
+                "auth",
+                &Auth {
+                    auth_type: "kubernetes".to_string(),
+                    path: (mount != "kubernetes").then(|| mount.to_string()),
+                    roles: vec![role.clone()],
+                },
+            );
+        };
+        let roles = self.child_sequence(item, "roles")?;
+        if self.find_in(&roles, &role.name).is_none() {
+            return self.push(&roles, role);
+        }
+        //keys are set one by one, each edit moving the lines of the role
+        for (key, value) in [
+            (
+                "bound_service_account_names",
+                &role.bound_service_account_names,
+            ),
+            (
+                "bound_service_account_namespaces",
+                &role.bound_service_account_namespaces,
+            ),
+            ("policies", &role.policies),
+        ] {
+            let item = self.find_auth(mount)?.unwrap();
+            let roles = self.child_sequence(item, "roles")?;
+            let existing = self.find_in(&roles, &role.name).unwrap();
+            self.set_field(&roles, existing, key, value)?;
+        }
+        Ok(())
+    }
+
+    //remove a role from the kubernetes auth method mounted at mount, keeping the method
+    pub fn remove_auth_role(&mut self, mount: &str, name: &str) -> Result<(), VaultValuesError> {
+        if self.child(self.external_config()?, "auth").is_none() {
+            return Ok(());
+        }
+        let Some(item) = self.find_auth(mount)? else {
+            return Ok(());
+        };
+        let roles = self.child_sequence(item, "roles")?;
+        if let Some(role) = self.find_in(&roles, name) {
+            self.remove(&roles, role);
+        }
+        Ok(())
+    }
+
+    //lines of the vault.externalConfig mapping
+    fn external_config(&self) -> Result<Range<usize>, VaultValuesError> {
+        let mut range = 0..self.lines.len();
+        for parent in ["vault", "externalConfig"] {
+            let line = self
+                .child(range.clone(), parent)
+                .ok_or_else(|| VaultValuesError::ParseError(format!("{parent} not found")))?;
+            range = self.value_range(line);
+        }
+        Ok(range)
+    }
+
+    //sequence under vault.externalConfig
+    fn sequence(&self, key: &str) -> Result<Sequence, VaultValuesError> {
+        let line = self
+            .child(self.external_config()?, key)
+            .ok_or_else(|| VaultValuesError::ParseError(format!("{key} not found")))?;
+        self.sequence_at(line)
+    }
+
+    //kubernetes auth method, its path defaults to the type
+    fn find_auth(&self, mount: &str) -> Result<Option<Range<usize>>, VaultValuesError> {
+        let auth = self.sequence("auth")?;
+        Ok(auth
+            .items
+            .iter()
+            .find(|item| {
+                let value = self.item_value(&auth, (*item).clone());
+                let auth_type = value.get("type").and_then(Value::as_str);
+                auth_type == Some("kubernetes")
+                    && value.get("path").and_then(Value::as_str).or(auth_type) == Some(mount)
+            })
+            .cloned())
+    }
+
+    //sequence held by a key of a sequence item
+    fn child_sequence(&self, item: Range<usize>, key: &str) -> Result<Sequence, VaultValuesError> {
+        let line = self
+            .child(item, key)
+            .ok_or_else(|| VaultValuesError::ParseError(format!("{key} not found")))?;
+        self.sequence_at(line)
+    }
+
+    fn sequence_at(&self, key_line: usize) -> Result<Sequence, VaultValuesError> {
+        let (key_indent, key, value) = key_of(&self.lines[key_line]).unwrap();
+        let inline = match strip_comment(value) {
+            "" => None,
+            flow => Some(serde_yaml::from_str::<Vec<String>>(flow).map_err(|_| {
+                VaultValuesError::Unsupported(format!(
+                    "{key} must be a block sequence or a flow sequence of names"
+                ))
+            })?),
+        };
+        let range = self.value_range(key_line);
+        let dash_indent = range
+            .clone()
+            .find(|&line| significant(&self.lines[line]))
+            .map(|line| indent(&self.lines[line]));
+        let dashes = range
+            .clone()
+            .filter(|&line| {
+                significant(&self.lines[line])
+                    && Some(indent(&self.lines[line])) == dash_indent
+                    && is_dash(&self.lines[line])
+            })
+            .collect::<Vec<usize>>();
+        let items = dashes
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let next = dashes.get(i + 1).copied().unwrap_or(range.end);
+                start..self.last_significant(start..next) + 1
+            })
+            .collect();
+        Ok(Sequence {
+            key_line,
+            key_indent,
+            inline,
+            dash_indent,
+            items,
+        })
+    }
+
+    //line of a direct child key in a mapping value or sequence item
+    fn child(&self, range: Range<usize>, key: &str) -> Option<usize> {
+        let first = range.clone().find(|&line| significant(&self.lines[line]))?;
+        let child_indent = key_of(&self.lines[first])?.0;
+        range.into_iter().find(|&line| {
+            significant(&self.lines[line])
+                && key_of(&self.lines[line])
+                    .is_some_and(|(indent, found, _)| indent == child_indent && found == key)
+        })
+    }
+
+    //lines holding the value of a key, up to its last significant line
+    fn value_range(&self, key_line: usize) -> Range<usize> {
+        let (key_indent, _, _) = key_of(&self.lines[key_line]).unwrap();
+        let mut end = key_line + 1;
+        for line in key_line + 1..self.lines.len() {
+            let text = &self.lines[line];
+            if !significant(text) {
+                continue;
+            }
+            let line_indent = indent(text);
+            if line_indent < key_indent || (line_indent == key_indent && !is_dash(text)) {
+                break;
+            }
+            end = line + 1;
+        }
+        key_line + 1..end
+    }
+
+    fn last_significant(&self, range: Range<usize>) -> usize {
+        range
+            .clone()
+            .rev()
+            .find(|&line| significant(&self.lines[line]))
+            .unwrap_or(range.start)
+    }
+
+    //parse a sequence item on its own
+    fn item_value(&self, sequence: &Sequence, item: Range<usize>) -> Value {
+        let dash_indent = sequence.dash_indent.unwrap_or_default();
+        let yaml = self.lines[item]
+            .iter()
+            .map(|line| &line[indent(line).min(dash_indent)..])
+            .collect::<Vec<&str>>()
+            .join("\n");
+        serde_yaml::from_str::<Vec<Value>>(&yaml)
+            .ok()
+            .and_then(|mut values| values.pop())
+            .unwrap_or(Value::Null)
+    }
+
+    fn find_item(&self, key: &str, name: &str) -> Result<Option<Range<usize>>, VaultValuesError> {
+        let sequence = self.sequence(key)?;
+        Ok(self.find_in(&sequence, name))
+    }
+
+    fn find_in(&self, sequence: &Sequence, name: &str) -> Option<Range<usize>> {
+        sequence
+            .items
+            .iter()
+            .find(|item| {
+                self.item_value(sequence, (*item).clone())
+                    .get("name")
+                    .and_then(Value::as_str)
+                    == Some(name)
+            })
+            .cloned()
+    }
+
+    fn scalars(&self, sequence: &Sequence) -> Vec<String> {
+        if let Some(names) = &sequence.inline {
+            return names.clone();
+        }
+        sequence
+            .items
+            .iter()
+            .map(|item| match self.item_value(sequence, item.clone()) {
+                Value::String(value) => value,
+                value => serde_yaml::to_string(&value).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn push_item<T: Serialize>(&mut self, key: &str, item: &T) -> Result<(), VaultValuesError> {
+        let sequence = self.sequence(key)?;
+        self.push(&sequence, item)
+    }
+
+    //rewrite a flow sequence of names, keeping a trailing comment
+    fn set_inline(&mut self, sequence: &Sequence, names: &[String]) {
+        let key_line = &mut self.lines[sequence.key_line];
+        let colon = key_line.find(':').unwrap();
+        let comment = key_line[colon..]
+            .find(" #")
+            .map(|position| key_line[colon + position..].to_string())
+            .unwrap_or_default();
+        let names = names
+            .iter()
+            .map(|name| serde_yaml::to_string(name).unwrap().trim_end().to_string())
+            .collect::<Vec<String>>();
+        key_line.truncate(colon + 1);
+        key_line.push_str(&format!(" [{}]{comment}", names.join(", ")));
+    }
+
+    //append an item after the last one, in the indentation already used by the sequence
+    fn push<T: Serialize>(
+        &mut self,
+        sequence: &Sequence,
+        item: &T,
+    ) -> Result<(), VaultValuesError> {
+        let yaml =
+            serde_yaml::to_string(item).map_err(|e| VaultValuesError::ParseError(e.to_string()))?;
+        let dash_indent = sequence.dash_indent.unwrap_or(sequence.key_indent + 2);
+        let lines = yaml.lines().enumerate().map(|(i, line)| {
+            if line.is_empty() {
+                String::new()
+            } else if i == 0 {
+                format!("{}- {line}", " ".repeat(dash_indent))
+            } else {
+                format!("{}{line}", " ".repeat(dash_indent + 2))
+            }
+        });
+        let at = sequence
+            .items
+            .last()
+            .map_or(sequence.key_line + 1, |item| item.end);
+        self.lines.splice(at..at, lines.collect::<Vec<String>>());
+        if sequence.inline.is_some() {
+            let key_line = &mut self.lines[sequence.key_line];
+            let colon = key_line.find(':').unwrap();
+            key_line.truncate(colon + 1);
+        }
+        Ok(())
+    }
+
+    //set a key of an item unless it already holds the value, leaving its other keys as they are
+    fn set_field<T: Serialize>(
+        &mut self,
+        sequence: &Sequence,
+        item: Range<usize>,
+        key: &str,
+        value: &T,
+    ) -> Result<(), VaultValuesError> {
+        let value =
+            serde_yaml::to_value(value).map_err(|e| VaultValuesError::ParseError(e.to_string()))?;
+        if self.item_value(sequence, item.clone()).get(key) == Some(&value) {
+            return Ok(());
+        }
+        let yaml =
+            serde_yaml::to_string(&serde_yaml::Mapping::from_iter([(Value::from(key), value)]))
+                .map_err(|e| VaultValuesError::ParseError(e.to_string()))?;
+        let (range, prefix, key_indent) = match self.child(item.clone(), key) {
+            Some(line) => {
+                let key_indent = key_of(&self.lines[line]).unwrap().0;
+                //a key on the dash line keeps the dash
+                let prefix = self.lines[line][..key_indent].to_string();
+                (line..self.value_range(line).end, prefix, key_indent)
+            }
+            None => {
+                let key_indent = key_of(&self.lines[item.start]).unwrap().0;
+                (item.end..item.end, " ".repeat(key_indent), key_indent)
+            }
+        };
+        let lines = yaml.lines().enumerate().map(|(i, line)| {
+            if line.is_empty() {
+                String::new()
+            } else if i == 0 {
+                format!("{prefix}{line}")
+            } else {
+                format!("{}{line}", " ".repeat(key_indent))
+            }
+        });
+        self.lines.splice(range, lines.collect::<Vec<String>>());
+        Ok(())
+    }
+
+    //remove an item, leaving an inline empty sequence when it was the last one
+    fn remove(&mut self, sequence: &Sequence, item: Range<usize>) {
+        self.lines.drain(item);
+        let remaining = self.value_range(sequence.key_line);
+        if !remaining.clone().any(|line| significant(&self.lines[line])) {
+            self.set_inline(sequence, &[]);
+        }
+    }
+}
+
+impl std::fmt::Display for VaultValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.lines.join("\n"))
+    }
+}
+
+fn is_dash(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed == "-" || trimmed.starts_with("- ")
+}
+
+//error enum
+#[derive(Debug, thiserror::Error)]
+pub enum VaultValuesError {
+    #[error("Could not parse vault values: {0}")]
+    ParseError(String),
+    #[error("Unsupported vault values: {0}")]
+    Unsupported(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: &str = include_str!("../tests/fixtures/vault_rbac_values.yaml");
+    const ADDED: &str = include_str!("../tests/fixtures/vault_rbac_values_added.yaml");
+
+    fn policy(name: &str) -> Policy {
+        Policy {
+            name: name.to_string(),
+            rules: format!("path \"secret/{name}/*\" {{\n  capabilities = [\"read\"]\n}}"),
+        }
+    }
+
+    fn alias(name: &str) -> GroupAlias {
+        GroupAlias {
+            name: name.to_string(),
+            mountpath: "oidc".to_string(),
+            group: name.to_string(),
+        }
+    }
+
+    fn add(values: &mut VaultValues, group: &str, name: &str) {
+        values.add_policy(&policy(name)).unwrap();
+        values.add_group_policy(group, name).unwrap();
+        values.add_group_alias(&alias(group)).unwrap();
+    }
+
+    fn remove(values: &mut VaultValues, group: &str, name: &str) {
+        values.remove_policy(name).unwrap();
+        values.remove_group_policy(name).unwrap();
+        if !values.has_group(group).unwrap() {
+            values.remove_group_alias(group).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_add_and_remove_match_golden_files() {
+        let mut values = VaultValues::parse(VALUES).unwrap();
+        add(&mut values, "demo@kyotu.tech", "demo_dev_access");
+        assert_eq!(values.to_string(), ADDED);
+        //adding twice changes nothing
+        add(&mut values, "demo@kyotu.tech", "demo_dev_access");
+        assert_eq!(values.to_string(), ADDED);
+
+        let mut values = VaultValues::parse(&values.to_string()).unwrap();
+        remove(&mut values, "demo@kyotu.tech", "demo_dev_access");
+        assert_eq!(values.to_string(), VALUES);
+    }
+
+    #[test]
+    fn test_existing_policy_rules_are_replaced() {
+        let mut values = VaultValues::parse(VALUES).unwrap();
+        let shop = Policy {
+            name: "shop_dev_access".to_string(),
+            ..policy("shop_dev")
+        };
+        values.add_policy(&shop).unwrap();
+        assert_eq!(
+            values.to_string(),
+            VALUES.replace(
+                "        rules: \"path \\\"secret/shop_dev/*\\\" {\\n  capabilities = [\\\"read\\\", \\\"list\\\"]\\n}\"\n",
+                "        rules: |-\n          path \"secret/shop_dev/*\" {\n            capabilities = [\"read\"]\n          }\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_existing_groups_keep_their_style() {
+        let mut values = VaultValues::parse(VALUES).unwrap();
+        add(&mut values, "shop@kyotu.tech", "shop_prod_access");
+        add(&mut values, "platform@kyotu.tech", "shop_prod_access");
+        let edited = values.to_string();
+        assert!(edited.contains("      policies: [shop_dev_access, shop_prod_access]\n"));
+        assert!(edited.contains("      - platform_admin\n      - shop_prod_access\n"));
+
+        remove(&mut values, "shop@kyotu.tech", "shop_prod_access");
+        assert_eq!(values.to_string(), VALUES);
+    }
+
+    #[test]
+    fn test_removing_last_entries_leaves_empty_sequences() {
+        let mut values = VaultValues::parse(
+            "vault:\n  externalConfig:\n    policies: []\n    groups: []\n    group-aliases: [] # none yet\n",
+        )
+        .unwrap();
+        add(&mut values, "demo@kyotu.tech", "demo_dev_access");
+        assert!(values
+            .to_string()
+            .contains("    groups:\n      - name: demo@kyotu.tech\n        policies:\n        - demo_dev_access\n"));
+
+        remove(&mut values, "demo@kyotu.tech", "demo_dev_access");
+        assert_eq!(
+            values.to_string(),
+            "vault:\n  externalConfig:\n    policies: []\n    groups: []\n    group-aliases: []\n"
+        );
+    }
+
+    fn role(name: &str) -> AuthRole {
+        AuthRole {
+            name: name.to_string(),
+            bound_service_account_names: vec!["*".to_string()],
+            bound_service_account_namespaces: vec![name.to_string()],
+            policies: vec![format!("{name}_workload")],
+        }
+    }
+
+    #[test]
+    fn test_auth_roles_are_added_to_the_kubernetes_method() {
+        let mut values = VaultValues::parse(VALUES).unwrap();
+        values.add_auth_role("kubernetes", &role("demo")).unwrap();
+        values.add_auth_role("kubernetes", &role("demo")).unwrap();
+        let edited = values.to_string();
+        assert!(edited.contains(
+            "            ttl: 1h\n          - name: demo\n            bound_service_account_names:\n            - '*'\n"
+        ));
+        assert_eq!(edited.matches("- name: demo\n").count(), 1);
+
+        values.remove_auth_role("kubernetes", "demo").unwrap();
+        assert_eq!(values.to_string(), VALUES);
+    }
+
+    #[test]
+    fn test_existing_auth_role_bindings_are_replaced() {
+        let mut values = VaultValues::parse(VALUES).unwrap();
+        let platform = AuthRole {
+            bound_service_account_names: vec!["deployer".to_string()],
+            policies: vec!["platform_admin".to_string()],
+            ..role("platform")
+        };
+        values.add_auth_role("kubernetes", &platform).unwrap();
+        assert_eq!(
+            values.to_string(),
+            VALUES.replace(
+                "            bound_service_account_names: [\"*\"]\n",
+                "            bound_service_account_names:\n            - deployer\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_auth_method_is_created_when_missing() {
+        let mut values = VaultValues::parse(
+            "vault:\n  externalConfig:\n    policies: []\n    groups: []\n    group-aliases: []\n",
+        )
+        .unwrap();
+        values.add_auth_role("k8s", &role("demo")).unwrap();
+        assert!(values.to_string().ends_with(
+            "    auth:\n      - type: kubernetes\n        path: k8s\n        roles:\n        - name: demo\n          bound_service_account_names:\n          - '*'\n          bound_service_account_namespaces:\n          - demo\n          policies:\n          - demo_workload\n"
+        ));
+        //other mounts are left alone
+        values.remove_auth_role("kubernetes", "demo").unwrap();
+        assert!(values.to_string().contains("- name: demo\n"));
+        values.remove_auth_role("k8s", "demo").unwrap();
+        assert!(values.to_string().contains("        roles: []\n"));
+    }
+
+    #[test]
+    fn test_parse_rejects_missing_external_config() {
+        assert!(matches!(
+            VaultValues::parse("vault:\n  server: {}\n"),
+            Err(VaultValuesError::ParseError(_))
+        ));
+    }
+}
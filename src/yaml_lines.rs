@@ -0,0 +1,39 @@
+//number of leading spaces of a line
+pub fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+//line holding yaml content, neither blank nor a comment
+pub fn significant(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.is_empty() && !trimmed.starts_with('#')
+}
+
+//inline value without its trailing comment
+pub fn strip_comment(value: &str) -> &str {
+    match value.find(" #") {
+        Some(position) => value[..position].trim(),
+        None if value.starts_with('#') => "",
+        None => value.trim(),
+    }
+}
+
+//indentation, key and inline value of a mapping line, looking through a sequence dash
+pub fn key_of(line: &str) -> Option<(usize, &str, &str)> {
+    let mut key_indent = indent(line);
+    let mut rest = &line[key_indent..];
+    if let Some(item) = rest.strip_prefix("- ") {
+        let item_indent = item.len() - item.trim_start().len();
+        key_indent += 2 + item_indent;
+        rest = &item[item_indent..];
+    }
+    let (key, value) = rest.split_once(':')?;
+    if !(value.is_empty() || value.starts_with(' ')) {
+        return None;
+    }
+    Some((
+        key_indent,
+        key.trim_matches(|c| c == '"' || c == '\''),
+        value.trim(),
+    ))
+}
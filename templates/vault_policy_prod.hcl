@@ -0,0 +1,8 @@
+path "secret/{{ secret_path }}/*" {
+  capabilities = ["read", "list"]
+}
+{% for extra in paths %}
+path {{ extra.path | json_encode() | safe }} {
+  capabilities = {{ extra.capabilities | json_encode() | safe }}
+}
+{% endfor %}